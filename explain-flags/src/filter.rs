@@ -0,0 +1,218 @@
+use std::io::{self, BufRead, Write};
+use std::iter::Peekable;
+use std::str::{CharIndices, FromStr};
+
+use crate::SamFlags;
+
+/// Boolean expression over flag names, e.g. `paired & !dup & (read1 | supplementary)`.
+///
+/// `!` binds tighter than `&`, `&` tighter than `|`.
+#[derive(Debug)]
+pub enum Expr {
+    Bit(SamFlags),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn matches(&self, flag: SamFlags) -> bool {
+        match self {
+            Expr::Bit(bit) => flag.contains(*bit),
+            Expr::Not(v) => !v.matches(flag),
+            Expr::And(a, b) => a.matches(flag) && b.matches(flag),
+            Expr::Or(a, b) => a.matches(flag) || b.matches(flag),
+        }
+    }
+}
+
+/// Recursive descent over the characters of an expression.
+struct Parser<'a> {
+    s: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+fn syntax(s: &str, at: usize, msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid filter `{}` at {}: {}", s, at, msg),
+    )
+}
+
+impl<'a> Parser<'a> {
+    /// Next non-whitespace character and its position, not consumed.
+    fn peek(&mut self) -> Option<(usize, char)> {
+        while let Some((_, c)) = self.chars.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.chars.next();
+        }
+        self.chars.peek().copied()
+    }
+
+    fn or(&mut self) -> io::Result<Expr> {
+        let mut expr = self.and()?;
+        while let Some((_, '|')) = self.peek() {
+            self.chars.next();
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> io::Result<Expr> {
+        let mut expr = self.not()?;
+        while let Some((_, '&')) = self.peek() {
+            self.chars.next();
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> io::Result<Expr> {
+        match self.peek() {
+            Some((_, '!')) => {
+                self.chars.next();
+                Ok(Expr::Not(Box::new(self.not()?)))
+            }
+            _ => self.atom(),
+        }
+    }
+
+    fn atom(&mut self) -> io::Result<Expr> {
+        match self.peek() {
+            Some((_, '(')) => {
+                self.chars.next();
+                let expr = self.or()?;
+                match self.peek() {
+                    Some((_, ')')) => {
+                        self.chars.next();
+                        Ok(expr)
+                    }
+                    Some((i, _)) => Err(syntax(self.s, i, "expect `)`")),
+                    None => Err(syntax(self.s, self.s.len(), "expect `)`")),
+                }
+            }
+            Some((start, c)) if c.is_ascii_alphanumeric() => {
+                let mut end = start;
+                while let Some((i, c)) = self.chars.peek().copied() {
+                    if !(c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    self.chars.next();
+                }
+                SamFlags::from_name(&self.s[start..end]).map(Expr::Bit)
+            }
+            Some((i, _)) => Err(syntax(self.s, i, "expect a flag name, `!` or `(`")),
+            None => Err(syntax(self.s, self.s.len(), "expect a flag name")),
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let mut parser = Parser {
+            s,
+            chars: s.char_indices().peekable(),
+        };
+        let expr = parser.or()?;
+        match parser.peek() {
+            Some((i, _)) => Err(syntax(s, i, "expect `&`, `|` or end")),
+            None => Ok(expr),
+        }
+    }
+}
+
+/// Copy sam lines whose flag matches, header lines always, empty lines never.
+///
+/// Returns number of records kept and read.
+pub fn filter<R: BufRead, W: Write>(
+    reader: R,
+    mut writer: W,
+    expr: &Expr,
+) -> io::Result<(u64, u64)> {
+    let (mut kept, mut total) = (0, 0);
+    for line in reader.lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        if !line.starts_with('@') {
+            let flag = match line.split('\t').nth(1) {
+                Some(v) => SamFlags::from_number(v)?,
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("no flag column in sam line `{}`", line),
+                    ))
+                }
+            };
+            total += 1;
+            if !expr.matches(flag) {
+                continue;
+            }
+            kept += 1;
+        }
+        writeln!(writer, "{}", line)?;
+    }
+    writer.flush()?;
+    Ok((kept, total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(expr: &str, flag: u16) -> bool {
+        let expr: Expr = expr.parse().unwrap();
+        expr.matches(SamFlags::from_bits_truncate(flag))
+    }
+
+    fn error(expr: &str) -> String {
+        expr.parse::<Expr>().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        // paired | (dup & read1), not (paired | dup) & read1
+        assert!(matches("paired | dup & read1", 0x1));
+        assert!(matches("dup & read1 | paired", 0x1));
+        assert!(!matches("paired | dup & read1", 0x400));
+        assert!(!matches("(paired | dup) & read1", 0x1));
+    }
+
+    #[test]
+    fn not_binds_tightest() {
+        // (!paired) & dup, not !(paired & dup)
+        assert!(matches("!paired & dup", 0x400));
+        assert!(!matches("!paired & dup", 0x1));
+        assert!(matches("!(paired & dup)", 0x1));
+        assert!(matches("!!paired", 0x1));
+        assert!(matches("paired & !dup & (read1 | supplementary)", 0x41));
+        assert!(!matches("paired & !dup & (read1 | supplementary)", 0x441));
+    }
+
+    #[test]
+    fn parenthesis_errors() {
+        assert_eq!(
+            error("(paired | dup"),
+            "invalid filter `(paired | dup` at 13: expect `)`"
+        );
+        assert_eq!(
+            error("(paired dup)"),
+            "invalid filter `(paired dup)` at 8: expect `)`"
+        );
+        assert_eq!(
+            error("paired)"),
+            "invalid filter `paired)` at 6: expect `&`, `|` or end"
+        );
+        assert_eq!(
+            error("()"),
+            "invalid filter `()` at 1: expect a flag name, `!` or `(`"
+        );
+        assert_eq!(error("!"), "invalid filter `!` at 1: expect a flag name");
+    }
+}
@@ -0,0 +1,234 @@
+//! Sam flags as a typed value: named constants, parsing from numbers or names,
+//! formatting to names, explanations and consistency validation.
+//!
+//! ```
+//! use ef::SamFlags;
+//!
+//! let flags: SamFlags = "paired,proper,mate-reverse,read1".parse().unwrap();
+//! assert_eq!(flags.bits(), 99);
+//! assert_eq!(flags.to_string(), "paired,proper-pair,mate-reverse,read1");
+//! assert!(flags.is_consistent());
+//! ```
+#[macro_use]
+extern crate bitflags;
+#[macro_use]
+extern crate lazy_static;
+
+pub mod filter;
+pub mod record;
+pub mod report;
+pub mod stats;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+bitflags! {
+    /// Bits of the sam FLAG field.
+    pub struct SamFlags: u16 {
+        const PAIRED = 0x1;
+        const PROPER_PAIR = 0x2;
+        const UNMAPPED = 0x4;
+        const MATE_UNMAPPED = 0x8;
+        const REVERSE = 0x10;
+        const MATE_REVERSE = 0x20;
+        const READ1 = 0x40;
+        const READ2 = 0x80;
+        const SECONDARY = 0x100;
+        const QCFAIL = 0x200;
+        const DUPLICATE = 0x400;
+        const SUPPLEMENTARY = 0x800;
+    }
+}
+
+/// Names of a flag, the first one canonical, and its explanation.
+pub struct Explain {
+    pub names: &'static [&'static str],
+    pub text: &'static str,
+}
+
+impl Explain {
+    fn new(names: &'static [&'static str], text: &'static str) -> Self {
+        Self { names, text }
+    }
+}
+
+// [Explanation source](https://broadinstitute.github.io/picard/explain-flags.html)
+// names follow samtools flags, lowercased and kebab-cased, plus short aliases
+lazy_static! {
+    pub static ref EXPLAINS: BTreeMap<SamFlags, Explain> = {
+        let mut m = BTreeMap::new();
+        m.insert(SamFlags::PAIRED, Explain::new(&["paired"], "read paired"));
+        m.insert(
+            SamFlags::PROPER_PAIR,
+            Explain::new(&["proper-pair", "proper"], "read mapped in proper pair"),
+        );
+        m.insert(
+            SamFlags::UNMAPPED,
+            Explain::new(&["unmapped", "unmap"], "read unmapped"),
+        );
+        m.insert(
+            SamFlags::MATE_UNMAPPED,
+            Explain::new(&["mate-unmapped", "munmap", "mate-unmap"], "mate unmapped"),
+        );
+        m.insert(
+            SamFlags::REVERSE,
+            Explain::new(&["reverse", "rev"], "read reverse strand"),
+        );
+        m.insert(
+            SamFlags::MATE_REVERSE,
+            Explain::new(&["mate-reverse", "mreverse", "mrev"], "mate reverse strand"),
+        );
+        m.insert(
+            SamFlags::READ1,
+            Explain::new(&["read1", "first"], "first in pair"),
+        );
+        m.insert(
+            SamFlags::READ2,
+            Explain::new(&["read2", "second"], "second in pair"),
+        );
+        m.insert(
+            SamFlags::SECONDARY,
+            Explain::new(&["secondary"], "not primary alignment"),
+        );
+        m.insert(
+            SamFlags::QCFAIL,
+            Explain::new(&["qcfail"], "read fails platform/vendor quality checks"),
+        );
+        m.insert(
+            SamFlags::DUPLICATE,
+            Explain::new(&["duplicate", "dup"], "read is PCR or optical duplicate"),
+        );
+        m.insert(
+            SamFlags::SUPPLEMENTARY,
+            Explain::new(&["supplementary", "supp"], "supplementary alignment"),
+        );
+        m
+    };
+}
+
+/// Sam spec constraints, violated when all bits of the first mask are set and none of
+/// the second.
+const RULES: [(u16, u16, &str); 10] = [
+    (0x2, 0x1, "proper pair set but read not paired"),
+    (0x8, 0x1, "mate unmapped set but read not paired"),
+    (0x20, 0x1, "mate reverse set but read not paired"),
+    (0x40, 0x1, "first in pair set but read not paired"),
+    (0x80, 0x1, "second in pair set but read not paired"),
+    (
+        0xc0,
+        0,
+        "both first and second in pair, only valid for templates of more than two segments",
+    ),
+    (0x6, 0, "proper pair set but read unmapped"),
+    (0xa, 0, "proper pair set but mate unmapped"),
+    (0x28, 0, "mate reverse set but mate unmapped"),
+    (0x804, 0, "supplementary alignment of an unmapped read"),
+];
+
+impl SamFlags {
+    /// Bit of a flag name or alias.
+    ///
+    /// Names are case insensitive and `_` may stand for `-`, so samtools names like
+    /// `PROPER_PAIR` work too.
+    pub fn from_name(name: &str) -> io::Result<Self> {
+        let normalized = name.trim().to_lowercase().replace('_', "-");
+        EXPLAINS
+            .iter()
+            .find(|(_, v)| v.names.contains(&normalized.as_str()))
+            .map(|(bit, _)| *bit)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown flag name `{}`", name),
+                )
+            })
+    }
+
+    /// Combine comma separated flag names, e.g. `paired,proper,mate-reverse,read1`.
+    pub fn from_names(names: &str) -> io::Result<Self> {
+        let mut flags = Self::empty();
+        for name in names.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+            flags |= Self::from_name(name)?;
+        }
+        Ok(flags)
+    }
+
+    /// Parse a flag in decimal, `0x` prefixed hex or `0` prefixed octal.
+    ///
+    /// Bits beyond the sam spec are refused.
+    pub fn from_number(s: &str) -> io::Result<Self> {
+        let s = s.trim();
+        let parsed = if let Some(v) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            u16::from_str_radix(v, 16)
+        } else if s.len() > 1 && s.starts_with('0') {
+            u16::from_str_radix(&s[1..], 8)
+        } else {
+            s.parse()
+        };
+        let bits = parsed.map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid flag `{}`: {}", s, e),
+            )
+        })?;
+        Self::from_bits(bits).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "invalid flag `{}`: unknown bits {:#x}",
+                    s,
+                    bits & !Self::all().bits()
+                ),
+            )
+        })
+    }
+
+    /// Canonical names of set bits.
+    pub fn names(self) -> Vec<&'static str> {
+        self.explains()
+            .filter(|(_, _, set)| *set)
+            .map(|(_, v, _)| v.names[0])
+            .collect()
+    }
+
+    /// Every known bit with its explanation and whether it is set, lowest bit first.
+    pub fn explains(self) -> impl Iterator<Item = (SamFlags, &'static Explain, bool)> {
+        EXPLAINS
+            .iter()
+            .map(move |(bit, v)| (*bit, v, self.contains(*bit)))
+    }
+
+    /// Explanations of every sam spec constraint the flags violate.
+    pub fn violations(self) -> impl Iterator<Item = &'static str> {
+        let flag = self.bits();
+        RULES
+            .iter()
+            .filter(move |(set, unset, _)| flag & set == *set && flag & unset == 0)
+            .map(|(_, _, text)| *text)
+    }
+
+    pub fn is_consistent(self) -> bool {
+        self.violations().next().is_none()
+    }
+}
+
+/// A number, or comma separated names if it does not start with a digit.
+impl FromStr for SamFlags {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        match s.trim().chars().next() {
+            Some(c) if c.is_ascii_digit() => Self::from_number(s),
+            _ => Self::from_names(s),
+        }
+    }
+}
+
+/// Comma separated canonical names, see `{:#x}` or `bits()` for the number.
+impl fmt::Display for SamFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.names().join(","))
+    }
+}
@@ -1,48 +1,241 @@
 #[macro_use]
-extern crate lazy_static;
+extern crate clap;
 
-use std::collections::BTreeMap;
-use std::env;
+use std::io::{self, BufRead, BufWriter, IsTerminal, Write};
+use std::process;
 
+use clap::App;
 use colored::*;
 
-// [Explanation source](https://broadinstitute.github.io/picard/explain-flags.html)
-lazy_static! {
-    static ref EXPLAINS: BTreeMap<u16, &'static str> = {
-        let mut m = BTreeMap::new();
-        m.insert(0x1, "read paired");
-        m.insert(0x2, "read mapped in proper pair");
-        m.insert(0x4, "read unmapped");
-        m.insert(0x8, "mate unmapped");
-        m.insert(0x10, "read reverse strand");
-        m.insert(0x20, "mate reverse strand");
-        m.insert(0x40, "first in pair");
-        m.insert(0x80, "second in pair");
-        m.insert(0x100, "not primary alignment");
-        m.insert(0x200, "read fails platform/vendor quality checks");
-        m.insert(0x400, "read is PCR or optical duplicate");
-        m.insert(0x800, "supplementary alignment");
-        m
-    };
-    static ref BITS: u32 = 11;
+use ef::filter::{filter, Expr};
+use ef::record::{self, is_record, Line};
+use ef::report::{Report, TSV_HEADER};
+use ef::stats::Stats;
+use ef::SamFlags;
+
+/// Check/cross list of every known bit.
+fn explain(flag: SamFlags) {
+    for (_, v, set) in flag.explains() {
+        match set {
+            true => println!("{} {}", "[\u{2713}]".bold(), v.text.green().bold()),
+            false => println!("{} {}", "[\u{2717}]".bold(), v.text.red().bold()),
+        }
+    }
+}
+
+/// One line per flag: decimal, hex and names of set bits.
+fn compact(flag: SamFlags) -> String {
+    format!("{}\t{:#x}\t{}", flag.bits(), flag.bits(), flag)
+}
+
+/// Print the explanation of a sam record, returns whether it is inconsistent.
+fn explain_record(line: &str) -> io::Result<bool> {
+    let mut inconsistent = false;
+    for v in record::explain(line)? {
+        match v {
+            Line::Field { name, value, note } => {
+                println!("{:<6} {} {}", name.bold(), value, note.dimmed())
+            }
+            Line::Detail(text) => println!("       {}", text),
+            Line::Warning(text) => {
+                println!("{} {}", "[!]".bold(), text.yellow().bold());
+                inconsistent = true;
+            }
+        }
+    }
+    Ok(inconsistent)
+}
+
+/// Whole sam records are only explained in prose.
+fn records_unstructured() -> Box<dyn std::error::Error> {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "sam records are explained in prose only, use `--column 2` for their flags with --json or --tsv",
+    )
+    .into()
+}
+
+/// Flag of a stdin line, the given 1-based tab separated column or the whole line.
+///
+/// Sam header and empty lines give none.
+fn flag_of_line(line: &str, column: Option<usize>) -> Result<Option<SamFlags>, io::Error> {
+    if line.is_empty() || line.starts_with('@') {
+        return Ok(None);
+    }
+    match column {
+        Some(n) => match line.split('\t').nth(n - 1) {
+            Some(v) => SamFlags::from_number(v).map(Some),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no column {} in line `{}`", n, line),
+            )),
+        },
+        None => line.parse().map(Some),
+    }
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut s: u16 = env::args().nth(1).expect("Expect a input").parse()?;
-    let mut d: u32 = 0;
-    while s > 0 {
-        match (s % 2, EXPLAINS.get(&2u16.pow(d))) {
-            (1, Some(v)) => println!("{} {}", "[\u{2713}]".bold(), v.green().bold()),
-            (0, Some(v)) => println!("{} {}", "[\u{2717}]".bold(), v.red().bold()),
-            _ => {}
-        };
-        s /= 2;
-        d += 1;
-    }
-    for i in d..=*BITS {
-        if let Some(v) = EXPLAINS.get(&2u16.pow(i)) {
-            println!("{} {}", "[\u{2717}]".bold(), v.red().bold());
-        };
+    let args = App::new(crate_name!())
+        .version(crate_version!())
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about(crate_description!())
+        .args_from_usage(
+            "
+            [json] -j, --json 'one json object per flag with every bit, its name, description and state'
+            [tsv] -t, --tsv 'one tsv row per flag bit with its name, state and description'
+            [compact] -c, --compact 'one line per flag: decimal, hex and set flag names'
+            [filter] -f, --filter=[EXPR] 'keep sam records from stdin whose flag matches, e.g. `paired & !dup & (read1 | supp)`'
+            [stats] -s, --stats 'tally flag bits and distinct flags of sam or bam records from stdin'
+            [column] -k, --column=[N] 'read flags from the N-th tab separated column of stdin, 2 for sam, whole line if not given'
+            [FLAG]... 'flags in decimal, 0x hex or 0 octal, comma separated names to encode, or whole sam records; read stdin, one per line, if none'
+            ",
+        )
+        .get_matches();
+    if !io::stdout().is_terminal() {
+        colored::control::set_override(false);
+    }
+    let json: bool = args.is_present("json");
+    let tsv: bool = args.is_present("tsv");
+    if json && tsv {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--json and --tsv are exclusive",
+        )
+        .into());
+    }
+    let mut compacted: bool = args.is_present("compact");
+    let column: Option<usize> = match args.value_of("column") {
+        Some(v) => match v.parse() {
+            Ok(n) if n > 0 => Some(n),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid column `{}`", v),
+                )
+                .into())
+            }
+        },
+        None => None,
+    };
+
+    if let Some(v) = args.value_of("filter") {
+        let expr: Expr = v.parse()?;
+        if io::stdin().is_terminal() {
+            eprintln!("{}", args.usage());
+            process::exit(1);
+        }
+        let stdout = io::stdout();
+        let (kept, total) = filter(io::stdin().lock(), BufWriter::new(stdout.lock()), &expr)?;
+        eprintln!("Kept {} of {} records ...", kept, total);
+        return Ok(());
+    }
+
+    if args.is_present("stats") {
+        if io::stdin().is_terminal() {
+            eprintln!("{}", args.usage());
+            process::exit(1);
+        }
+        let stdout = io::stdout();
+        Stats::tally(io::stdin().lock())?.write(BufWriter::new(stdout.lock()))?;
+        return Ok(());
+    }
+
+    let inputs: Vec<&str> = args
+        .values_of("FLAG")
+        .map_or_else(Vec::new, |v| v.collect());
+    if inputs.is_empty() {
+        if io::stdin().is_terminal() {
+            eprintln!("{}", args.usage());
+            process::exit(1);
+        }
+        let stdout = io::stdout();
+        let mut writer = BufWriter::new(stdout.lock());
+        let mut invalid = false;
+        if tsv {
+            writeln!(writer, "{}", TSV_HEADER)?;
+        }
+        for line in io::stdin().lock().lines() {
+            let line = line?;
+            let line = line.trim_end_matches('\r');
+            if column.is_none() && is_record(line) {
+                if json || tsv {
+                    return Err(records_unstructured());
+                }
+                writer.flush()?;
+                invalid |= explain_record(line)?;
+                println!();
+                continue;
+            }
+            if let Some(flag) = flag_of_line(line, column)? {
+                if json {
+                    Report::new(flag).write_json(&mut writer)?;
+                } else if tsv {
+                    Report::new(flag).write_tsv(&mut writer)?;
+                } else {
+                    writeln!(writer, "{}", compact(flag))?;
+                }
+                for text in flag.violations() {
+                    if !json {
+                        eprintln!("{}: {}", flag.bits(), text);
+                    }
+                    invalid = true;
+                }
+            }
+        }
+        writer.flush()?;
+        if invalid {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // names only encode, unless explanation asked for
+    let encoding = !compacted
+        && inputs
+            .iter()
+            .all(|v| !v.starts_with(|c: char| c.is_ascii_digit()));
+    compacted |= inputs.len() > 1 && encoding;
+    if (json || tsv) && inputs.iter().any(|v| is_record(v)) {
+        return Err(records_unstructured());
+    }
+    let mut invalid = false;
+    if tsv {
+        println!("{}", TSV_HEADER);
+    }
+    for input in inputs.iter() {
+        if is_record(input) {
+            invalid |= explain_record(input)?;
+            continue;
+        }
+        let flag: SamFlags = input.parse()?;
+        if json {
+            Report::new(flag).write_json(io::stdout())?;
+        } else if tsv {
+            Report::new(flag).write_tsv(io::stdout())?;
+        } else if compacted {
+            println!("{}", compact(flag));
+        } else if encoding {
+            println!("{}\t{:#x}", flag.bits(), flag.bits());
+        } else {
+            if inputs.len() > 1 {
+                println!("{}", format!("{} {:#x}", flag.bits(), flag.bits()).bold());
+            }
+            explain(flag);
+        }
+        for text in flag.violations() {
+            invalid = true;
+            // json reports violations inline
+            if json {
+                continue;
+            } else if compacted || encoding || tsv {
+                eprintln!("{}: {}", flag.bits(), text);
+            } else {
+                println!("{} {}", "[!]".bold(), text.yellow().bold());
+            }
+        }
+    }
+    if invalid {
+        process::exit(1);
     }
     Ok(())
 }
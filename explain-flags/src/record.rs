@@ -0,0 +1,248 @@
+use std::io;
+
+use crate::SamFlags;
+
+/// Cigar operations: code, description, consumes query, consumes reference.
+pub const CIGAR_OPS: [(char, &str, bool, bool); 9] = [
+    ('M', "alignment match", true, true),
+    ('I', "insertion to the reference", true, false),
+    ('D', "deletion from the reference", false, true),
+    ('N', "skipped region from the reference", false, true),
+    ('S', "soft clipping", true, false),
+    ('H', "hard clipping", false, false),
+    ('P', "padding", false, false),
+    ('=', "sequence match", true, true),
+    ('X', "sequence mismatch", true, true),
+];
+
+/// Optional tags of the sam spec and common aligners.
+pub const TAGS: [(&str, &str); 18] = [
+    ("AS", "alignment score"),
+    ("BC", "barcode sequence"),
+    ("CB", "cell barcode"),
+    ("HI", "query hit index"),
+    ("MC", "cigar of the mate"),
+    ("MD", "mismatching positions and deleted reference bases"),
+    ("MQ", "mapping quality of the mate"),
+    ("NH", "number of reported alignments of the query"),
+    ("NM", "edit distance to the reference"),
+    ("OQ", "original base quality"),
+    ("PG", "program"),
+    ("RG", "read group"),
+    ("RX", "molecular barcode sequence"),
+    ("SA", "other canonical alignments in a chimeric alignment"),
+    ("UB", "molecular barcode, corrected"),
+    ("XA", "alternative hits, bwa"),
+    ("XS", "suboptimal alignment score, bwa"),
+    ("CO", "free-text comment"),
+];
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// A line of a record explanation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line {
+    /// a field or optional tag, its value and what it means
+    Field {
+        name: String,
+        value: String,
+        note: String,
+    },
+    /// detail of the field before, a cigar operation or a chimeric part
+    Detail(String),
+    /// an inconsistency of the record
+    Warning(String),
+}
+
+fn field(name: &str, value: &str, note: &str) -> Line {
+    Line::Field {
+        name: name.to_string(),
+        value: value.to_string(),
+        note: note.to_string(),
+    }
+}
+
+/// Operations of a cigar string as length and operation.
+pub fn cigar_ops(cigar: &str) -> io::Result<Vec<(u32, char)>> {
+    let mut ops = Vec::new();
+    let mut len = String::new();
+    for c in cigar.chars() {
+        if c.is_ascii_digit() {
+            len.push(c);
+            continue;
+        }
+        match len.parse() {
+            Ok(n) if CIGAR_OPS.iter().any(|v| v.0 == c) => ops.push((n, c)),
+            _ => return Err(invalid(format!("invalid cigar `{}`", cigar))),
+        }
+        len.clear();
+    }
+    if !len.is_empty() {
+        return Err(invalid(format!("invalid cigar `{}`", cigar)));
+    }
+    Ok(ops)
+}
+
+/// Query and reference lengths consumed by cigar operations.
+pub fn consumed(ops: &[(u32, char)]) -> (u32, u32) {
+    ops.iter().fold((0, 0), |(query, reference), (n, c)| {
+        let op = CIGAR_OPS.iter().find(|v| v.0 == *c).unwrap();
+        (
+            query + if op.2 { *n } else { 0 },
+            reference + if op.3 { *n } else { 0 },
+        )
+    })
+}
+
+/// Description of an optional tag, if known.
+pub fn tag_note(name: &str) -> Option<&'static str> {
+    TAGS.iter().find(|v| v.0 == name).map(|v| v.1)
+}
+
+fn mapq_note(mapq: u8) -> String {
+    match mapq {
+        255 => "mapping quality not available".to_string(),
+        0 => "mapped equally well elsewhere, or unmapped".to_string(),
+        q => format!(
+            "probability the position is wrong {:.2e}",
+            10f64.powf(-(q as f64) / 10.0)
+        ),
+    }
+}
+
+/// Whether the input looks like a whole sam record rather than a flag.
+pub fn is_record(s: &str) -> bool {
+    s.split('\t').count() >= 11 || s.split_whitespace().count() >= 11
+}
+
+/// Explain a sam record line field by field.
+///
+/// Fields are tab separated, or whitespace separated if pasted without tabs. The
+/// record is inconsistent if any line is a `Line::Warning`.
+pub fn explain(line: &str) -> io::Result<Vec<Line>> {
+    let fields: Vec<&str> = match line.contains('\t') {
+        true => line.split('\t').collect(),
+        false => line.split_whitespace().collect(),
+    };
+    if fields.len() < 11 {
+        return Err(invalid(format!(
+            "sam record needs 11 fields, got {}",
+            fields.len()
+        )));
+    }
+    let mut lines: Vec<Line> = Vec::new();
+
+    lines.push(field("QNAME", fields[0], "query template name"));
+    let flag = SamFlags::from_number(fields[1])?;
+    lines.push(field(
+        "FLAG",
+        &format!("{} {:#x}", flag.bits(), flag.bits()),
+        &flag.to_string(),
+    ));
+    for text in flag.violations() {
+        lines.push(Line::Warning(text.to_string()));
+    }
+    let unmapped = flag.contains(SamFlags::UNMAPPED);
+    lines.push(field("RNAME", fields[2], "reference name"));
+    lines.push(field("POS", fields[3], "1-based leftmost mapping position"));
+    let mapq: u8 = fields[4]
+        .parse()
+        .map_err(|_| invalid(format!("invalid mapq `{}`", fields[4])))?;
+    lines.push(field("MAPQ", fields[4], &mapq_note(mapq)));
+
+    let mut query: Option<u32> = None;
+    if fields[5] == "*" {
+        lines.push(field("CIGAR", "*", "unavailable"));
+    } else {
+        let ops = cigar_ops(fields[5])?;
+        let (q, r) = consumed(&ops);
+        lines.push(field(
+            "CIGAR",
+            fields[5],
+            &format!("query {} reference {}", q, r),
+        ));
+        for (n, c) in ops.iter() {
+            let op = CIGAR_OPS.iter().find(|v| v.0 == *c).unwrap();
+            lines.push(Line::Detail(format!(
+                "{:>6}{} {:<34} query {:<6} reference {}",
+                n,
+                c,
+                op.1,
+                if op.2 { *n } else { 0 },
+                if op.3 { *n } else { 0 }
+            )));
+        }
+        query = Some(q);
+    }
+
+    let rnext = match fields[6] {
+        "=" => "same reference as the read",
+        "*" => "unavailable",
+        _ => "reference of the mate",
+    };
+    lines.push(field("RNEXT", fields[6], rnext));
+    lines.push(field("PNEXT", fields[7], "1-based position of the mate"));
+    lines.push(field("TLEN", fields[8], "observed template length"));
+    if flag.contains(SamFlags::PAIRED)
+        && !unmapped
+        && !flag.contains(SamFlags::MATE_UNMAPPED)
+        && fields[6] == "="
+    {
+        let pos: i64 = fields[3].parse().unwrap_or(0);
+        let pnext: i64 = fields[7].parse().unwrap_or(0);
+        let orientation = match (
+            flag.contains(SamFlags::REVERSE),
+            flag.contains(SamFlags::MATE_REVERSE),
+        ) {
+            (false, false) | (true, true) => "tandem, both on the same strand",
+            (false, true) if pos <= pnext => "FR, facing inwards",
+            (true, false) if pnext <= pos => "FR, facing inwards",
+            _ => "RF, facing outwards",
+        };
+        lines.push(field(
+            "PAIR",
+            orientation,
+            "orientation inferred from strands and positions",
+        ));
+    }
+
+    lines.push(field(
+        "SEQ",
+        fields[9],
+        &format!("{} bases", fields[9].len()),
+    ));
+    if let (Some(q), false) = (query, fields[9] == "*") {
+        if q as usize != fields[9].len() {
+            lines.push(Line::Warning(format!(
+                "sequence length {} differs from cigar query length {}",
+                fields[9].len(),
+                q
+            )));
+        }
+    }
+    lines.push(field("QUAL", fields[10], "phred+33 base qualities"));
+    if fields[10] != "*" && fields[9] != "*" && fields[10].len() != fields[9].len() {
+        lines.push(Line::Warning(
+            "quality and sequence lengths differ".to_string(),
+        ));
+    }
+
+    for tag in fields[11..].iter() {
+        let mut parts = tag.splitn(3, ':');
+        let (name, kind, value) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(n), Some(k), Some(v)) => (n, k, v),
+            _ => return Err(invalid(format!("invalid optional field `{}`", tag))),
+        };
+        let note = tag_note(name).unwrap_or("unknown tag");
+        lines.push(field(name, &format!("{}:{}", kind, value), note));
+        // chimeric parts and alternative hits, one per line
+        if name == "SA" || name == "XA" {
+            for hit in value.split(';').filter(|v| !v.is_empty()) {
+                lines.push(Line::Detail(hit.replace(',', " ")));
+            }
+        }
+    }
+    Ok(lines)
+}
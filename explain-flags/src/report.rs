@@ -0,0 +1,62 @@
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use crate::SamFlags;
+
+/// Header of `Report::write_tsv` rows.
+pub const TSV_HEADER: &str = "#flag\tbit\tname\tset\tdescription";
+
+/// A flag bit of a machine readable explanation.
+#[derive(Debug, Serialize)]
+pub struct Bit {
+    pub bit: String,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub set: bool,
+}
+
+/// Machine readable explanation of a flag.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub flag: u16,
+    pub hex: String,
+    pub bits: Vec<Bit>,
+    pub violations: Vec<&'static str>,
+}
+
+impl Report {
+    pub fn new(flag: SamFlags) -> Self {
+        Self {
+            flag: flag.bits(),
+            hex: format!("{:#x}", flag.bits()),
+            bits: flag
+                .explains()
+                .map(|(bit, v, set)| Bit {
+                    bit: format!("{:#x}", bit.bits()),
+                    name: v.names[0],
+                    description: v.text,
+                    set,
+                })
+                .collect(),
+            violations: flag.violations().collect(),
+        }
+    }
+
+    /// One json object per line.
+    pub fn write_json<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{}", serde_json::to_string(self)?)
+    }
+
+    /// One tsv row per bit, see `TSV_HEADER`.
+    pub fn write_tsv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for v in self.bits.iter() {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}",
+                self.flag, v.bit, v.name, v.set as u8, v.description
+            )?;
+        }
+        Ok(())
+    }
+}
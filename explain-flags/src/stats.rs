@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use flate2::read::MultiGzDecoder;
+
+use crate::{SamFlags, EXPLAINS};
+
+/// Counts of records per flag bit and per distinct flag.
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub total: u64,
+    pub bits: BTreeMap<SamFlags, u64>,
+    pub flags: BTreeMap<SamFlags, u64>,
+}
+
+fn percent(count: u64, total: u64) -> f64 {
+    match total {
+        0 => 0.0,
+        _ => count as f64 * 100.0 / total as f64,
+    }
+}
+
+impl Stats {
+    pub fn add(&mut self, flag: SamFlags) {
+        self.total += 1;
+        *self.flags.entry(flag).or_default() += 1;
+        for bit in EXPLAINS.keys().filter(|bit| flag.contains(**bit)) {
+            *self.bits.entry(*bit).or_default() += 1;
+        }
+    }
+
+    /// Tally records of a sam or bam stream, plain or gzip/bgzf compressed.
+    pub fn tally<'a, R: Read + 'a>(reader: R) -> io::Result<Self> {
+        let mut reader: Box<dyn BufRead + 'a> = Box::new(BufReader::new(reader));
+        if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+            reader = Box::new(BufReader::new(MultiGzDecoder::new(reader)));
+        }
+        let mut stats = Self::default();
+        if reader.fill_buf()?.starts_with(b"BAM\x01") {
+            stats.add_bam(reader)?;
+        } else {
+            stats.add_sam(reader)?;
+        }
+        Ok(stats)
+    }
+
+    fn add_sam<R: BufRead>(&mut self, reader: R) -> io::Result<()> {
+        for line in reader.lines() {
+            let line = line?;
+            if line.is_empty() || line.starts_with('@') {
+                continue;
+            }
+            match line.split('\t').nth(1) {
+                Some(v) => self.add(SamFlags::from_number(v)?),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("no flag column in sam line `{}`", line),
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add_bam<R: Read>(&mut self, mut reader: R) -> io::Result<()> {
+        let mut buf = [0u8; 4];
+        reader.read_exact(&mut buf)?;
+        // header text, then name and length of every reference
+        let text = read_i32(&mut reader)?;
+        skip(&mut reader, text)?;
+        for _ in 0..read_i32(&mut reader)? {
+            let name = read_i32(&mut reader)?;
+            skip(&mut reader, name + 4)?;
+        }
+        let mut record: Vec<u8> = Vec::new();
+        loop {
+            // a clean end of stream is only allowed between records
+            match reader.read(&mut buf[..1])? {
+                0 => break,
+                _ => reader.read_exact(&mut buf[1..])?,
+            }
+            let size = i32::from_le_bytes(buf);
+            if size < 16 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("bam record of {} bytes is too short", size),
+                ));
+            }
+            record.resize(size as usize, 0);
+            reader.read_exact(&mut record)?;
+            // refID, pos, l_read_name, mapq, bin, n_cigar_op precede flag
+            self.add(SamFlags::from_bits_truncate(u16::from_le_bytes([
+                record[14], record[15],
+            ])));
+        }
+        Ok(())
+    }
+
+    /// Table of counts and percentages per flag bit, then per distinct flag.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "#total\t{}", self.total)?;
+        writeln!(writer, "#bit\tname\tcount\tpercent\tdescription")?;
+        for (bit, v) in EXPLAINS.iter() {
+            let count = self.bits.get(bit).copied().unwrap_or(0);
+            writeln!(
+                writer,
+                "{:#x}\t{}\t{}\t{:.2}%\t{}",
+                bit.bits(),
+                v.names[0],
+                count,
+                percent(count, self.total),
+                v.text
+            )?;
+        }
+        writeln!(writer, "#flag\thex\tcount\tpercent\tnames")?;
+        for (flag, count) in self.flags.iter() {
+            writeln!(
+                writer,
+                "{}\t{:#x}\t{}\t{:.2}%\t{}",
+                flag.bits(),
+                flag.bits(),
+                count,
+                percent(*count, self.total),
+                flag
+            )?;
+        }
+        writer.flush()
+    }
+}
+
+fn read_i32<R: Read>(reader: &mut R) -> io::Result<i32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(i32::from_le_bytes(buf))
+}
+
+fn skip<R: Read>(reader: &mut R, n: i32) -> io::Result<()> {
+    let n = u64::try_from(n)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "negative length in bam header"))?;
+    if io::copy(&mut reader.take(n), &mut io::sink())? != n {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "truncated bam header",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use super::*;
+
+    /// A bam stream with a header, one reference and a bare record per flag.
+    fn bam(flags: &[u16]) -> Vec<u8> {
+        let mut bam = b"BAM\x01".to_vec();
+        let text = b"@HD\tVN:1.6\n";
+        bam.extend_from_slice(&(text.len() as i32).to_le_bytes());
+        bam.extend_from_slice(text);
+        bam.extend_from_slice(&1i32.to_le_bytes());
+        bam.extend_from_slice(&3i32.to_le_bytes());
+        bam.extend_from_slice(b"c1\0");
+        bam.extend_from_slice(&1000i32.to_le_bytes());
+        for flag in flags {
+            let mut record = vec![0u8; 32];
+            record[8] = 2; // l_read_name
+            record[14..16].copy_from_slice(&flag.to_le_bytes());
+            record.extend_from_slice(b"r\0");
+            bam.extend_from_slice(&(record.len() as i32).to_le_bytes());
+            bam.extend_from_slice(&record);
+        }
+        bam
+    }
+
+    #[test]
+    fn tally_bam() {
+        let stats = Stats::tally(&bam(&[99, 147, 4])[..]).unwrap();
+        assert_eq!(stats.total, 3);
+        assert_eq!(stats.flags[&SamFlags::from_bits_truncate(99)], 1);
+        assert_eq!(stats.bits[&SamFlags::PAIRED], 2);
+        assert_eq!(stats.bits[&SamFlags::UNMAPPED], 1);
+    }
+
+    #[test]
+    fn tally_gzipped_bam() {
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(&bam(&[16])).unwrap();
+        let stats = Stats::tally(&gz.finish().unwrap()[..]).unwrap();
+        assert_eq!(stats.total, 1);
+        assert_eq!(stats.bits[&SamFlags::REVERSE], 1);
+    }
+
+    #[test]
+    fn truncated_bam_record() {
+        let mut cut = bam(&[99]);
+        cut.truncate(cut.len() - 1);
+        assert!(Stats::tally(&cut[..]).is_err());
+        let mut short = bam(&[]);
+        short.extend_from_slice(&8i32.to_le_bytes());
+        assert!(Stats::tally(&short[..]).is_err());
+    }
+}
@@ -0,0 +1,140 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use bzip2::read::MultiBzDecoder;
+use bzip2::write::BzEncoder;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use serde::Serialize;
+
+use crate::BUFFER_SIZE;
+
+/// Compression of a fastq file.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Plain,
+    Gzip,
+    Bzip2,
+    Zstd,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Format::Plain => "plain",
+            Format::Gzip => "gzip",
+            Format::Bzip2 => "bzip2",
+            Format::Zstd => "zstd",
+        })
+    }
+}
+
+impl FromStr for Format {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        match s {
+            "plain" => Ok(Format::Plain),
+            "gzip" => Ok(Format::Gzip),
+            "bzip2" => Ok(Format::Bzip2),
+            "zstd" => Ok(Format::Zstd),
+            v => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "unknown format `{}`, expect `gzip`, `bzip2`, `zstd` or `plain`",
+                    v
+                ),
+            )),
+        }
+    }
+}
+
+/// Encoder that must be finished to write its trailer.
+pub trait Encoder: Write {
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+impl Encoder for File {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl Encoder for GzEncoder<File> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        GzEncoder::finish(*self).map(|_| ())
+    }
+}
+
+impl Encoder for BzEncoder<File> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        BzEncoder::finish(*self).map(|_| ())
+    }
+}
+
+impl Encoder for zstd::Encoder<'static, File> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        zstd::Encoder::finish(*self).map(|_| ())
+    }
+}
+
+impl Format {
+    /// Format by magic bytes at the start of a stream.
+    fn sniff(head: &[u8]) -> Self {
+        match head {
+            [0x1f, 0x8b, ..] => Format::Gzip,
+            [b'B', b'Z', b'h', ..] => Format::Bzip2,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Format::Zstd,
+            _ => Format::Plain,
+        }
+    }
+
+    /// Output format by extension, `.bz2`, `.zst`, `.fastq` or `.fq`, gzip otherwise.
+    pub fn from_extension<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|v| v.to_str()) {
+            Some("bz2") => Format::Bzip2,
+            Some("zst") => Format::Zstd,
+            Some("fastq") | Some("fq") => Format::Plain,
+            _ => Format::Gzip,
+        }
+    }
+
+    /// Format of a file by its content.
+    pub fn detect<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut head: Vec<u8> = Vec::with_capacity(4);
+        File::open(path)?.take(4).read_to_end(&mut head)?;
+        Ok(Self::sniff(&head))
+    }
+
+    /// Detect the format of a stream and decompress it.
+    pub fn decoder<'a, R: Read + 'a>(reader: R) -> io::Result<(Self, Box<dyn Read + 'a>)> {
+        let mut reader = BufReader::with_capacity(BUFFER_SIZE, reader);
+        let format = Self::sniff(reader.fill_buf()?);
+        let decoded: Box<dyn Read> = match format {
+            Format::Plain => Box::new(reader),
+            Format::Gzip => Box::new(MultiGzDecoder::new(reader)),
+            Format::Bzip2 => Box::new(MultiBzDecoder::new(reader)),
+            Format::Zstd => Box::new(zstd::Decoder::with_buffer(reader)?),
+        };
+        Ok((format, decoded))
+    }
+
+    /// Open a fastq file of any format for reading, decompressed.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn Read>> {
+        Self::decoder(File::open(path)?).map(|(_, v)| v)
+    }
+
+    /// Compress into `file` with this format, fast levels.
+    pub fn encoder(self, file: File) -> io::Result<Box<dyn Encoder>> {
+        Ok(match self {
+            Format::Plain => Box::new(file),
+            Format::Gzip => Box::new(GzEncoder::new(file, flate2::Compression::fast())),
+            Format::Bzip2 => Box::new(BzEncoder::new(file, bzip2::Compression::fast())),
+            Format::Zstd => Box::new(zstd::Encoder::new(file, 1)?),
+        })
+    }
+}
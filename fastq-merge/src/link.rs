@@ -0,0 +1,136 @@
+use std::fs;
+use std::io;
+#[cfg(target_family = "unix")]
+use std::os::unix::fs::symlink;
+#[cfg(target_family = "windows")]
+use std::os::windows::fs::symlink_file as symlink;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use crate::manifest::md5sum;
+
+/// How the sources of a single group are put in place of the outputs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Link {
+    /// symlink to the canonical source path
+    Absolute,
+    /// symlink relative to the output directory, survives moving both together
+    Relative,
+    Hard,
+    /// copy-on-write clone, plain copy where the file system has no support
+    Reflink,
+    Copy,
+}
+
+impl FromStr for Link {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        match s {
+            "absolute" => Ok(Link::Absolute),
+            "relative" => Ok(Link::Relative),
+            "hard" => Ok(Link::Hard),
+            "reflink" => Ok(Link::Reflink),
+            "copy" => Ok(Link::Copy),
+            v => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "unknown link `{}`, expect `absolute`, `relative`, `hard`, `reflink` or `copy`",
+                    v
+                ),
+            )),
+        }
+    }
+}
+
+/// Path of `target` relative to directory `base`, both absolute.
+fn relative_to(target: &Path, base: &Path) -> PathBuf {
+    let target: Vec<Component> = target.components().collect();
+    let base: Vec<Component> = base.components().collect();
+    let common = target
+        .iter()
+        .zip(base.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    base[common..]
+        .iter()
+        .map(|_| Component::ParentDir)
+        .chain(target[common..].iter().copied())
+        .collect()
+}
+
+fn mismatch(src: &Path, dst: &Path, reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "{} does not match {}: {}",
+            dst.display(),
+            src.display(),
+            reason
+        ),
+    )
+}
+
+impl Link {
+    /// Put `src` at `dst`, then check that `dst` really is `src` or an identical copy.
+    ///
+    /// `final_dst` is where `dst` ends up, relative symlinks are computed from its directory.
+    pub fn create(self, src: &Path, dst: &Path, final_dst: &Path) -> io::Result<()> {
+        let src = src.canonicalize()?;
+        match self {
+            Link::Absolute => symlink(&src, dst)?,
+            Link::Relative => {
+                let parent = match final_dst.parent() {
+                    Some(v) if v != Path::new("") => v.canonicalize()?,
+                    _ => PathBuf::from(".").canonicalize()?,
+                };
+                symlink(relative_to(&src, &parent), dst)?
+            }
+            Link::Hard => fs::hard_link(&src, dst)?,
+            Link::Reflink => {
+                if reflink::reflink_or_copy(&src, dst)?.is_some() {
+                    eprintln!("Reflink not supported, copied {} ...", src.display());
+                }
+            }
+            Link::Copy => {
+                fs::copy(&src, dst)?;
+            }
+        }
+        self.verify(&src, dst)
+    }
+
+    fn verify(self, src: &Path, dst: &Path) -> io::Result<()> {
+        match self {
+            Link::Absolute | Link::Relative => {
+                if dst.canonicalize()? != src {
+                    return Err(mismatch(src, dst, "symlink resolves elsewhere"));
+                }
+            }
+            Link::Hard => {
+                #[cfg(target_family = "unix")]
+                {
+                    use std::os::unix::fs::MetadataExt;
+                    let (a, b) = (fs::metadata(src)?, fs::metadata(dst)?);
+                    if (a.dev(), a.ino()) != (b.dev(), b.ino()) {
+                        return Err(mismatch(src, dst, "not the same inode"));
+                    }
+                }
+                #[cfg(not(target_family = "unix"))]
+                {
+                    if fs::metadata(src)?.len() != fs::metadata(dst)?.len() {
+                        return Err(mismatch(src, dst, "size differs"));
+                    }
+                }
+            }
+            Link::Reflink | Link::Copy => {
+                if fs::metadata(src)?.len() != fs::metadata(dst)?.len() {
+                    return Err(mismatch(src, dst, "size differs"));
+                }
+                if md5sum(src)? != md5sum(dst)? {
+                    return Err(mismatch(src, dst, "md5 differs"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
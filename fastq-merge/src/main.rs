@@ -1,25 +1,35 @@
-#![feature(btree_drain_filter, map_first_last, with_options)]
 #[macro_use]
 extern crate clap;
+extern crate bzip2;
 extern crate flate2;
 extern crate glob;
+extern crate md5;
+extern crate reflink;
+extern crate serde;
+extern crate serde_json;
+extern crate zstd;
+
+mod codec;
+mod link;
+mod manifest;
+mod merge;
+mod output;
+mod plan;
 
 use std::collections::BTreeMap;
 use std::error::Error;
-use std::fs::{self, File};
-use std::io::{self, BufWriter, Read, Write};
-#[cfg(target_family = "unix")]
-use std::os::unix::fs::symlink;
-#[cfg(target_family = "windows")]
-use std::os::windows::fs::symlink_file as symlink;
-use std::path::PathBuf;
+use std::io;
+use std::path::Path;
 use std::result::Result;
 
-use clap::{App, AppSettings};
-use flate2::read::MultiGzDecoder;
-use flate2::write::GzEncoder;
-use flate2::Compression;
-use glob::glob;
+use clap::{App, AppSettings, Arg};
+
+use codec::Format;
+use link::Link;
+use manifest::Manifest;
+use merge::{merge, write_ranges, Tag};
+use output::{check_overwrite, Staged};
+use plan::{Action, Group, Plan, ROLES};
 
 const BUFFER_SIZE: usize = 32 * 1024;
 
@@ -27,91 +37,213 @@ fn none_err() -> io::Error {
     io::Error::new(io::ErrorKind::InvalidData, "NoneError")
 }
 
-#[allow(unused_must_use)]
 fn main() -> Result<(), Box<dyn Error>> {
     let args = App::new(crate_name!())
         .setting(AppSettings::ArgRequiredElseHelp)
         .version(crate_version!())
-        .author(crate_authors!(";"))
+        .author(env!("CARGO_PKG_AUTHORS"))
         .about(crate_description!())
         .args_from_usage(
             "
-            <read1> -1=[FILE] 'read1 output path of concated pair'
-            <read2> -2=[FILE] 'read2 output path of concated pair'
-            <srcdir> 'fastq pair source directory to concat, fastq glob `*_R[12].fastq.gz` or `*_R[12].fq.gz`'
+            [read2] -2, --read2=[FILE] 'read2 output path of concated reads, if paired'
+            [read3] -3, --read3=[FILE] 'read3 output path of concated reads, if any'
+            [index1] --index1=[FILE] 'index read1 output path of concated reads, if any'
+            [index2] --index2=[FILE] 'index read2 output path of concated reads, if any'
+            [recursive] -r, --recursive 'also search subdirectories of source directories'
+            [link] -l, --link=[MODE] 'how a single group is put in place, `absolute` (default) or `relative` symlink, `hard` link, `reflink` or `copy`'
+            [output_format] -O, --output-format=[FORMAT] 'output compression, `gzip`, `bzip2`, `zstd` or `plain`, by output extension if not given, gzip if unknown'
+            [force] -f, --force 'overwrite existing outputs'
+            [tag] -t, --tag=[TAG] 'append source `lane` or `file` to read header comments as `src=<tag>`'
+            [paired] -p, --paired 'read all reads of a group in lockstep, fail where read names diverge'
+            [ranges] --ranges 'write record ranges of each source to `<output>.ranges.tsv`'
+            [verify] --verify 'count records and bases of sources and outputs, fail if they disagree'
+            [manifest] -m, --manifest=[FILE] 'write verification manifest in json, implies --verify'
+            [ignore_unmatched] --ignore-unmatched 'merge the read set most groups share and ignore groups with other reads, instead of failing'
+            [dry_run] -n, --dry-run 'print planned groups and merge order without touching any output'
+            [plan_format] --plan-format=[FORMAT] 'dry-run output format, `table` (default) or `json`'
+            <srcdir>... 'fastq source directories to concat, fastq glob `*_{R1,R2,R3,I1,I2}.f{ast,}q[.gz|.bz2|.zst]`'
             ",
         )
+        .arg(
+            Arg::from_usage("[read1] -1, --read1=[FILE] 'read1 output path of concated reads'")
+                .required_unless("dry_run"),
+        )
         .get_matches();
-    let srcdir: &str = args.value_of("srcdir").ok_or_else(none_err)?;
-    let read1: &str = args.value_of("read1").ok_or_else(none_err)?;
-    let read2: &str = args.value_of("read2").ok_or_else(none_err)?;
+    let srcdirs: Vec<&str> = args.values_of("srcdir").ok_or_else(none_err)?.collect();
+    let manifest: Option<&str> = args.value_of("manifest");
+    let verify: bool = args.is_present("verify") || manifest.is_some();
+    let tag: Option<Tag> = args.value_of("tag").map(str::parse).transpose()?;
+    let ranges: bool = args.is_present("ranges");
+    let paired: bool = args.is_present("paired");
+    let link: Link = args.value_of("link").unwrap_or("absolute").parse()?;
+    let output_format: Option<Format> =
+        args.value_of("output_format").map(str::parse).transpose()?;
 
-    let mut collector: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
-    // *_R[12].f{ast,}q.gz
-    for alias in &["fastq", "fq"] {
-        let slen = 7 + alias.len();
-        for i in 1..=2 {
-            for path in glob(&format!("{}/*_R{}.{}.gz", srcdir, i, alias))? {
-                let path: String = path?.to_string_lossy().to_string();
-                let prefix: String = path.clone()[..(path.len() - slen)]
-                    .rsplit('/')
-                    .next()
-                    .ok_or_else(none_err)?
-                    .to_owned();
-                collector
-                    .entry(prefix)
-                    .or_insert_with(BTreeMap::new)
-                    .entry(format!("read{}", i))
-                    .or_insert(path);
+    let mut plan = Plan::collect(&srcdirs, args.is_present("recursive"))?;
+    // output formats known so far, a dry run may go without outputs
+    let formats: BTreeMap<String, Format> = plan
+        .roles
+        .iter()
+        .filter_map(|role| {
+            output_format
+                .or_else(|| args.value_of(role).map(Format::from_extension))
+                .map(|v| (role.to_owned(), v))
+        })
+        .collect();
+    let recompressed = plan.recompress(&formats)?;
+    if args.is_present("dry_run") {
+        match args.value_of("plan_format").unwrap_or("table") {
+            "json" => println!("{}", serde_json::to_string_pretty(&plan)?),
+            "table" => print!("{}", plan.table()),
+            v => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown plan format `{}`", v),
+                )
+                .into())
             }
         }
-        // remove singles
-        collector.drain_filter(|_, v| v.len() <= 1);
+        return Ok(());
     }
-
+    // groups with other reads are only left out when asked to
+    let ignored: Vec<&Group> = plan
+        .groups
+        .iter()
+        .filter(|g| g.action == Action::Ignore)
+        .collect();
+    if !ignored.is_empty() && !args.is_present("ignore_unmatched") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "groups {} have other reads than {}, merged groups must share them, see --dry-run or --ignore-unmatched",
+                ignored
+                    .iter()
+                    .map(|g| format!("{} ({})", g.prefix, g.files.keys().cloned().collect::<Vec<String>>().join("+")))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                plan.roles.join("+")
+            ),
+        )
+        .into());
+    }
+    for group in ignored {
+        for path in group.files.values() {
+            eprintln!(
+                "Ignoring {}, reads of {} not in {} ...",
+                path,
+                group.prefix,
+                plan.roles.join("+")
+            );
+        }
+    }
+    for dup in plan.duplicates.iter() {
+        eprintln!(
+            "Skipping {}, duplicate of {} by {} ...",
+            dup.path, dup.of, dup.reason
+        );
+    }
+    let collector: Vec<&Group> = plan.merged().collect();
     if collector.is_empty() {
-        return Err(io::Error::new(io::ErrorKind::NotFound, "Gzipped fastq not found").into());
-    } else if collector.len() == 1 {
-        let (_, pair) = collector.first_key_value().ok_or_else(none_err)?;
-        fs::remove_file(read1);
-        symlink(
-            PathBuf::from(&pair[&"read1".to_owned()]).canonicalize()?,
-            read1,
-        )?;
-        fs::remove_file(read2);
-        symlink(
-            PathBuf::from(&pair[&"read2".to_owned()]).canonicalize()?,
-            read2,
-        )?;
-        return Ok(());
+        return Err(io::Error::new(io::ErrorKind::NotFound, "Fastq not found").into());
+    }
+
+    // one output per role found
+    let mut outputs: BTreeMap<String, String> = BTreeMap::new();
+    for (_, role) in ROLES.iter() {
+        match (plan.roles.iter().any(|v| v == role), args.value_of(role)) {
+            (true, Some(v)) => {
+                outputs.insert(role.to_string(), v.to_owned());
+            }
+            (true, None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} found but no --{} output given", role, role),
+                )
+                .into())
+            }
+            (false, Some(v)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("no {} found for output {}", role, v),
+                )
+                .into())
+            }
+            (false, None) => {}
+        }
     }
+    for (role, source, format) in recompressed.iter() {
+        eprintln!("Recompressing {} from {} to {} ...", role, source, format);
+    }
+    // a single group is linked unless it has to be recompressed
+    let relink: bool = collector[0].action == Action::Link;
 
-    let mut stream1 = BufWriter::new(GzEncoder::new(
-        File::with_options().write(true).create(true).open(read1)?,
-        Compression::fast(),
-    ));
-    let mut stream2 = BufWriter::new(GzEncoder::new(
-        File::with_options().write(true).create(true).open(read2)?,
-        Compression::fast(),
-    ));
-    for i in collector.values() {
-        let mut buffer: Vec<u8> = vec![0; BUFFER_SIZE];
-        eprintln!("Concating {} ...", &i[&"read1".to_owned()]);
-        let mut reader = MultiGzDecoder::new(File::open(&i[&"read1".to_owned()])?);
-        let mut size = BUFFER_SIZE;
-        while size != 0 {
-            size = reader.read(&mut buffer)?;
-            stream1.write_all(&buffer[..size])?;
+    let sidecars: BTreeMap<String, String> = outputs
+        .iter()
+        .filter(|_| ranges && !relink)
+        .map(|(role, v)| (role.to_owned(), format!("{}.ranges.tsv", v)))
+        .collect();
+    check_overwrite(
+        outputs
+            .values()
+            .chain(sidecars.values())
+            .map(|v| v.as_str())
+            .chain(manifest),
+        args.is_present("force"),
+    )?;
+    // written aside, renamed into place once complete and verified
+    let staged: BTreeMap<String, Staged> = outputs
+        .iter()
+        .map(|(role, output)| (role.to_owned(), Staged::new(output)))
+        .collect();
+    let mut staged_sidecars: Vec<Staged> = Vec::new();
+    if relink {
+        if tag.is_some() || ranges || paired {
+            eprintln!(
+                "Single group is linked not recompressed, skip --tag, --ranges and --paired ..."
+            );
+        }
+        for (role, output) in staged.iter() {
+            link.create(
+                Path::new(&collector[0].files[role]),
+                output.tmp(),
+                output.path(),
+            )?;
         }
-        eprintln!("Concating {} ...", &i[&"read2".to_owned()]);
-        reader = MultiGzDecoder::new(File::open(&i[&"read2".to_owned()])?);
-        size = BUFFER_SIZE;
-        while size != 0 {
-            size = reader.read(&mut buffer)?;
-            stream2.write_all(&buffer[..size])?;
+    } else {
+        for (role, rows) in merge(&collector, &staged, &formats, tag, ranges, paired)? {
+            if let Some(path) = sidecars.get(&role) {
+                let sidecar = Staged::new(path);
+                write_ranges(sidecar.create()?, &rows)?;
+                staged_sidecars.push(sidecar);
+            }
         }
     }
-    stream1.into_inner()?.try_finish()?;
-    stream2.into_inner()?.try_finish()?;
+
+    if verify {
+        let tmps: BTreeMap<String, String> = staged
+            .iter()
+            .map(|(role, v)| (role.to_owned(), v.tmp().to_string_lossy().to_string()))
+            .collect();
+        let mut proof = Manifest::verify(&collector, &tmps)?;
+        for (role, entry) in proof.outputs.iter_mut() {
+            entry.path = staged[role].path().to_string_lossy().to_string();
+        }
+        if let Some(path) = manifest {
+            let staged = Staged::new(path);
+            serde_json::to_writer_pretty(staged.create()?, &proof)?;
+            staged.commit()?;
+        }
+        if !proof.verified {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, proof.errors.join("\n")).into());
+        }
+        eprintln!(
+            "Verified {} reads per output from {} source groups",
+            proof.outputs.values().next().map_or(0, |v| v.records),
+            proof.inputs.len()
+        );
+    }
+    for output in staged.into_values().chain(staged_sidecars) {
+        output.commit()?;
+    }
     Ok(())
 }
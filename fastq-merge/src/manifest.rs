@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::codec::Format;
+use crate::plan;
+use crate::BUFFER_SIZE;
+
+/// Record and base count of a fastq stream.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct Tally {
+    pub records: u64,
+    pub bases: u64,
+}
+
+impl Tally {
+    /// Count records and bases of an uncompressed fastq stream, 4 lines per record.
+    pub fn from_reader<R: Read>(reader: R) -> io::Result<Self> {
+        let mut tally = Tally::default();
+        let mut reader = BufReader::with_capacity(BUFFER_SIZE, reader);
+        let mut line: Vec<u8> = Vec::new();
+        let mut nline: u64 = 0;
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            if nline % 4 == 1 {
+                tally.bases += line
+                    .iter()
+                    .rev()
+                    .skip_while(|&&c| c == b'\n' || c == b'\r')
+                    .count() as u64;
+            }
+            nline += 1;
+        }
+        if nline % 4 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated fastq record",
+            ));
+        }
+        tally.records = nline / 4;
+        Ok(tally)
+    }
+}
+
+impl std::ops::AddAssign for Tally {
+    fn add_assign(&mut self, other: Self) {
+        self.records += other.records;
+        self.bases += other.bases;
+    }
+}
+
+/// Reader that feeds every byte passing through it into a md5 context.
+struct Md5Reader<R> {
+    inner: R,
+    context: md5::Context,
+}
+
+impl<R: Read> Read for Md5Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.inner.read(buf)?;
+        self.context.consume(&buf[..size]);
+        Ok(size)
+    }
+}
+
+/// Md5 of the raw bytes of a file.
+pub fn md5sum<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let mut hashed = Md5Reader {
+        inner: File::open(path)?,
+        context: md5::Context::new(),
+    };
+    io::copy(&mut hashed, &mut io::sink())?;
+    Ok(format!("{:x}", hashed.context.compute()))
+}
+
+/// Count and checksum of one fastq file.
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub path: String,
+    pub format: Format,
+    pub records: u64,
+    pub bases: u64,
+    pub md5: String,
+}
+
+impl Entry {
+    /// Decompress `path` once, counting its records and hashing its raw bytes.
+    pub fn inspect<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut hashed = Md5Reader {
+            inner: File::open(path.as_ref())?,
+            context: md5::Context::new(),
+        };
+        let (format, tally) = {
+            let (format, decoded) = Format::decoder(&mut hashed)?;
+            (format, Tally::from_reader(decoded)?)
+        };
+        // drain trailing bytes the decoder did not ask for
+        io::copy(&mut hashed, &mut io::sink())?;
+        Ok(Self {
+            path: path.as_ref().to_string_lossy().to_string(),
+            format,
+            records: tally.records,
+            bases: tally.bases,
+            md5: format!("{:x}", hashed.context.compute()),
+        })
+    }
+
+    pub fn tally(&self) -> Tally {
+        Tally {
+            records: self.records,
+            bases: self.bases,
+        }
+    }
+}
+
+/// Sources of one prefix, in merge order.
+#[derive(Debug, Serialize)]
+pub struct Group {
+    pub prefix: String,
+    pub files: BTreeMap<String, Entry>,
+}
+
+/// Proof of a merge: every input in merge order, the outputs and what was checked.
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    pub inputs: Vec<Group>,
+    pub outputs: BTreeMap<String, Entry>,
+    pub errors: Vec<String>,
+    pub verified: bool,
+}
+
+/// Describe differing record counts of same-sized streams, none if all agree.
+fn out_of_sync(entries: &BTreeMap<String, Entry>) -> Option<String> {
+    let records = entries.values().next()?.records;
+    if entries.values().all(|v| v.records == records) {
+        return None;
+    }
+    Some(
+        entries
+            .iter()
+            .map(|(role, v)| format!("{} has {} records", role, v.records))
+            .collect::<Vec<String>>()
+            .join(", "),
+    )
+}
+
+impl Manifest {
+    /// Count sources and outputs, then check that sums add up and mates stay in sync.
+    pub fn verify(
+        collector: &[&plan::Group],
+        outputs: &BTreeMap<String, String>,
+    ) -> io::Result<Self> {
+        let mut errors: Vec<String> = Vec::new();
+        let mut inputs: Vec<Group> = Vec::with_capacity(collector.len());
+        let mut sums: BTreeMap<&str, Tally> = BTreeMap::new();
+        for plan::Group { prefix, files, .. } in collector.iter() {
+            eprintln!("Verifying {} ...", prefix);
+            let mut group = Group {
+                prefix: prefix.to_owned(),
+                files: BTreeMap::new(),
+            };
+            for (role, path) in files.iter() {
+                let entry = Entry::inspect(path)?;
+                *sums.entry(role).or_default() += entry.tally();
+                group.files.insert(role.to_owned(), entry);
+            }
+            if let Some(v) = out_of_sync(&group.files) {
+                errors.push(format!("{}: mates out of sync, {}", prefix, v));
+            }
+            inputs.push(group);
+        }
+        let mut merged: BTreeMap<String, Entry> = BTreeMap::new();
+        for (role, path) in outputs.iter() {
+            let output = Entry::inspect(path)?;
+            let sum = sums.get(role.as_str()).copied().unwrap_or_default();
+            if output.tally() != sum {
+                errors.push(format!(
+                    "{}: expect {} records and {} bases, found {} records and {} bases",
+                    output.path, sum.records, sum.bases, output.records, output.bases
+                ));
+            }
+            merged.insert(role.to_owned(), output);
+        }
+        if let Some(v) = out_of_sync(&merged) {
+            errors.push(format!("outputs out of sync, {}", v));
+        }
+        Ok(Self {
+            inputs,
+            outputs: merged,
+            verified: errors.is_empty(),
+            errors,
+        })
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::codec::{Encoder, Format};
+use crate::output::Staged;
+use crate::plan::Group;
+use crate::BUFFER_SIZE;
+
+/// Source tag appended to read header comments.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tag {
+    /// prefix of the source group, e.g. `S1_L001`
+    Lane,
+    /// file name of the source
+    File,
+}
+
+impl FromStr for Tag {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        match s {
+            "lane" => Ok(Tag::Lane),
+            "file" => Ok(Tag::File),
+            v => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown tag `{}`, expect `lane` or `file`", v),
+            )),
+        }
+    }
+}
+
+impl Tag {
+    fn of(self, group: &Group, path: &str) -> String {
+        match self {
+            Tag::Lane => group.prefix.to_owned(),
+            Tag::File => Path::new(path)
+                .file_name()
+                .map_or_else(|| path.to_owned(), |v| v.to_string_lossy().to_string()),
+        }
+    }
+}
+
+/// Records `start..end` (0-based, end exclusive) of a merged output copied from `source`.
+#[derive(Debug)]
+pub struct Range {
+    pub source: String,
+    pub prefix: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+/// One fastq record, 4 lines with line endings kept.
+#[derive(Debug, Default)]
+struct Record {
+    lines: [Vec<u8>; 4],
+}
+
+impl Record {
+    /// Read next record, false at end of stream.
+    fn read<R: BufRead>(&mut self, reader: &mut R) -> io::Result<bool> {
+        for (i, line) in self.lines.iter_mut().enumerate() {
+            line.clear();
+            if reader.read_until(b'\n', line)? == 0 {
+                return match i {
+                    0 => Ok(false),
+                    _ => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "truncated fastq record",
+                    )),
+                };
+            }
+        }
+        Ok(true)
+    }
+
+    /// Read name, header up to the first whitespace without `/1`, `/2` mate suffix.
+    fn name(&self) -> &[u8] {
+        let header = &self.lines[0];
+        let end = header
+            .iter()
+            .position(|c| c.is_ascii_whitespace())
+            .unwrap_or(header.len());
+        let name = &header[..end];
+        match name {
+            [head @ .., b'/', b'1'..=b'3'] => head,
+            _ => name,
+        }
+    }
+
+    /// Write record, appending ` src=<tag>` to the header if given.
+    fn write<W: Write>(&self, writer: &mut W, tag: Option<&str>) -> io::Result<()> {
+        match tag {
+            Some(v) => {
+                let header = &self.lines[0];
+                let end = header
+                    .iter()
+                    .rposition(|&c| c != b'\n' && c != b'\r')
+                    .map_or(0, |i| i + 1);
+                writer.write_all(&header[..end])?;
+                writer.write_all(b" src=")?;
+                writer.write_all(v.as_bytes())?;
+                writer.write_all(b"\n")?;
+            }
+            None => writer.write_all(&self.lines[0])?,
+        }
+        for line in self.lines[1..].iter() {
+            writer.write_all(line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Copy fastq records one by one, tagging headers if given.
+///
+/// Returns number of records copied.
+fn copy_records<R: Read, W: Write>(
+    reader: R,
+    writer: &mut W,
+    tag: Option<&str>,
+) -> io::Result<u64> {
+    let mut reader = BufReader::with_capacity(BUFFER_SIZE, reader);
+    let mut record = Record::default();
+    let mut records: u64 = 0;
+    while record.read(&mut reader)? {
+        record.write(writer, tag)?;
+        records += 1;
+    }
+    Ok(records)
+}
+
+/// A source read in lockstep with its mates.
+struct Mate<'a> {
+    path: &'a str,
+    reader: BufReader<Box<dyn Read>>,
+    tag: Option<String>,
+    record: Record,
+}
+
+/// Copy all roles of a group record by record in lockstep, failing where read names
+/// diverge or one source ends before the others.
+///
+/// Returns number of records copied per role.
+fn copy_lockstep<W: Write>(
+    group: &Group,
+    streams: &mut BTreeMap<&str, W>,
+    tag: Option<Tag>,
+) -> Result<u64, Box<dyn Error>> {
+    let mut mates: Vec<Mate> = Vec::new();
+    for role in streams.keys() {
+        let path = &group.files[*role];
+        eprintln!("Concating {} ...", path);
+        mates.push(Mate {
+            path,
+            reader: BufReader::with_capacity(BUFFER_SIZE, Format::open(path)?),
+            tag: tag.map(|v| v.of(group, path)),
+            record: Record::default(),
+        });
+    }
+    let mut count: u64 = 0;
+    loop {
+        let mut ended: Vec<&str> = Vec::new();
+        for mate in mates.iter_mut() {
+            if !mate.record.read(&mut mate.reader)? {
+                ended.push(mate.path);
+            }
+        }
+        if ended.len() == mates.len() {
+            break;
+        } else if !ended.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: {} ended at record {} while mates did not",
+                    group.prefix,
+                    ended.join(", "),
+                    count + 1
+                ),
+            )
+            .into());
+        }
+        let first = &mates[0];
+        if let Some(mate) = mates[1..]
+            .iter()
+            .find(|v| v.record.name() != first.record.name())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: mates diverge at record {}, `{}` in {} but `{}` in {}",
+                    group.prefix,
+                    count + 1,
+                    String::from_utf8_lossy(first.record.name()),
+                    first.path,
+                    String::from_utf8_lossy(mate.record.name()),
+                    mate.path
+                ),
+            )
+            .into());
+        }
+        for (mate, stream) in mates.iter().zip(streams.values_mut()) {
+            mate.record.write(stream, mate.tag.as_deref())?;
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Concatenate every role of each group, in group order, into its output compressed
+/// as given in `formats`.
+///
+/// Sources are copied record by record when tagging, tracking ranges or checking
+/// mates in lockstep (`paired`), otherwise byte by byte. Ranges are returned per role.
+pub fn merge(
+    collector: &[&Group],
+    outputs: &BTreeMap<String, Staged>,
+    formats: &BTreeMap<String, Format>,
+    tag: Option<Tag>,
+    ranges: bool,
+    paired: bool,
+) -> Result<BTreeMap<String, Vec<Range>>, Box<dyn Error>> {
+    let mut streams: BTreeMap<&str, BufWriter<Box<dyn Encoder>>> = BTreeMap::new();
+    for (role, output) in outputs.iter() {
+        streams.insert(
+            role,
+            BufWriter::new(formats[role].encoder(output.create()?)?),
+        );
+    }
+    let mut tracked: BTreeMap<String, Vec<Range>> = BTreeMap::new();
+    let mut track = |role: &str, path: &str, group: &Group, records: u64| {
+        let rows = tracked.entry(role.to_string()).or_default();
+        let start = rows.last().map_or(0, |v| v.end);
+        rows.push(Range {
+            source: path.to_owned(),
+            prefix: group.prefix.to_owned(),
+            start,
+            end: start + records,
+        });
+    };
+    let mut buffer: Vec<u8> = vec![0; BUFFER_SIZE];
+    for group in collector.iter() {
+        if paired {
+            let records = copy_lockstep(group, &mut streams, tag)?;
+            for role in streams.keys() {
+                track(role, &group.files[*role], group, records);
+            }
+            continue;
+        }
+        // every role in the same order for each group
+        for (role, stream) in streams.iter_mut() {
+            let path = &group.files[*role];
+            eprintln!("Concating {} ...", path);
+            let mut reader = Format::open(path)?;
+            if tag.is_none() && !ranges {
+                let mut size = BUFFER_SIZE;
+                while size != 0 {
+                    size = reader.read(&mut buffer)?;
+                    stream.write_all(&buffer[..size])?;
+                }
+                continue;
+            }
+            let records = copy_records(reader, stream, tag.map(|v| v.of(group, path)).as_deref())?;
+            track(role, path, group, records);
+        }
+    }
+    for stream in streams.into_values() {
+        stream.into_inner().map_err(|e| e.into_error())?.finish()?;
+    }
+    Ok(tracked)
+}
+
+/// Write record ranges of sources as tsv.
+pub fn write_ranges<W: Write>(writer: W, rows: &[Range]) -> io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    writeln!(writer, "#source\tprefix\tstart\tend")?;
+    for row in rows.iter() {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}",
+            row.source, row.prefix, row.start, row.end
+        )?;
+    }
+    writer.flush()
+}
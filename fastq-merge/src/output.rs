@@ -0,0 +1,83 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Output written to a temporary sibling first and renamed into place by `commit`.
+///
+/// The temporary file is removed if dropped uncommitted, e.g. when merge fails.
+pub struct Staged {
+    path: PathBuf,
+    tmp: PathBuf,
+    committed: bool,
+}
+
+impl Staged {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let name = path
+            .file_name()
+            .map_or_else(String::new, |v| v.to_string_lossy().to_string());
+        let tmp = path.with_file_name(format!(".{}.{}.tmp", name, process::id()));
+        Self {
+            path,
+            tmp,
+            committed: false,
+        }
+    }
+
+    /// Final path of the output.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Temporary path to write to.
+    pub fn tmp(&self) -> &Path {
+        &self.tmp
+    }
+
+    /// Create the temporary file, truncated.
+    pub fn create(&self) -> io::Result<File> {
+        File::create(&self.tmp)
+    }
+
+    /// Atomically replace the final path with the temporary file.
+    pub fn commit(mut self) -> io::Result<()> {
+        fs::rename(&self.tmp, &self.path)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.tmp);
+        }
+    }
+}
+
+/// Refuse to touch any existing path unless forced.
+pub fn check_overwrite<'a, I: IntoIterator<Item = &'a str>>(
+    paths: I,
+    force: bool,
+) -> io::Result<()> {
+    if force {
+        return Ok(());
+    }
+    let existing: Vec<&str> = paths
+        .into_iter()
+        .filter(|v| fs::symlink_metadata(v).is_ok())
+        .collect();
+    if existing.is_empty() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "output exists, use --force to overwrite: {}",
+                existing.join(", ")
+            ),
+        ))
+    }
+}
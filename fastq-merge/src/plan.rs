@@ -0,0 +1,310 @@
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use glob::glob;
+use serde::Serialize;
+
+use crate::codec::Format;
+use crate::manifest::md5sum;
+use crate::none_err;
+
+/// What happens to the files of a group.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Link,
+    Concatenate,
+    Ignore,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Action::Link => "link",
+            Action::Concatenate => "concatenate",
+            Action::Ignore => "ignore",
+        })
+    }
+}
+
+/// Read roles, as fastq name tag and output key, in the order outputs are listed.
+pub const ROLES: [(&str, &str); 5] = [
+    ("R1", "read1"),
+    ("R2", "read2"),
+    ("R3", "read3"),
+    ("I1", "index1"),
+    ("I2", "index2"),
+];
+
+/// Fastq files sharing one prefix, keyed by role, e.g. `read1`, `index1`.
+#[derive(Debug, Serialize)]
+pub struct Group {
+    pub prefix: String,
+    /// 1-based position in merge order, none if ignored.
+    pub order: Option<usize>,
+    pub action: Action,
+    pub files: BTreeMap<String, String>,
+}
+
+/// A source file skipped because the same data was already collected.
+#[derive(Debug, Serialize)]
+pub struct Duplicate {
+    pub path: String,
+    /// path of the file kept instead
+    pub of: String,
+    /// `path` or `checksum`
+    pub reason: &'static str,
+}
+
+/// Groups to merge in merge order, followed by unmatched groups to ignore.
+#[derive(Debug, Serialize)]
+pub struct Plan {
+    /// roles every merged group has, one output each
+    pub roles: Vec<String>,
+    pub groups: Vec<Group>,
+    pub duplicates: Vec<Duplicate>,
+}
+
+impl Plan {
+    /// Glob `*_{R1,R2,R3,I1,I2}.f{ast,}q[.gz|.bz2|.zst]` in every source directory and
+    /// group files by prefix.
+    ///
+    /// Earlier directories take precedence, a file reached twice or a group whose
+    /// files all have the content of the same roles of an earlier group is a
+    /// duplicate, a group only partly so is an error. A prefix already taken by another
+    /// directory is grouped by its path instead, e.g. `run2/S1_L001`, and a prefix
+    /// and read seen twice in one directory with different content is an error.
+    pub fn collect(srcdirs: &[&str], recursive: bool) -> Result<Self, Box<dyn Error>> {
+        let mut collector: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+        let mut duplicates: Vec<Duplicate> = Vec::new();
+        let mut seen: HashMap<PathBuf, String> = HashMap::new();
+        // directory of the first files of each prefix
+        let mut owners: HashMap<String, String> = HashMap::new();
+        // (size, path, group, role) of collected files in discovery order
+        let mut sizes: Vec<(u64, String, String, String)> = Vec::new();
+        let depth = if recursive { "**/" } else { "" };
+        for srcdir in srcdirs {
+            for (alias, ext) in ["fastq", "fq"]
+                .iter()
+                .flat_map(|a| [".gz", ".bz2", ".zst", ""].iter().map(move |e| (a, e)))
+            {
+                for (tag, role) in ROLES.iter() {
+                    let suffix = format!("_{}.{}{}", tag, alias, ext);
+                    for path in glob(&format!("{}/{}*{}", srcdir, depth, suffix))? {
+                        let path: String = path?.to_string_lossy().to_string();
+                        let canonical = PathBuf::from(&path).canonicalize()?;
+                        if let Some(of) = seen.get(&canonical) {
+                            duplicates.push(Duplicate {
+                                path,
+                                of: of.to_owned(),
+                                reason: "path",
+                            });
+                            continue;
+                        }
+                        seen.insert(canonical.clone(), path.clone());
+                        let stem: &str = &path[..(path.len() - suffix.len())];
+                        let (dir, prefix) = stem.rsplit_once('/').ok_or_else(none_err)?;
+                        // runs reuse sample names, the same prefix elsewhere is other data
+                        let owner = owners
+                            .entry(prefix.to_owned())
+                            .or_insert_with(|| dir.to_owned());
+                        let key: &str = if owner == dir { prefix } else { stem };
+                        match collector
+                            .entry(key.to_owned())
+                            .or_default()
+                            .entry(role.to_string())
+                        {
+                            Entry::Occupied(v) => {
+                                if md5sum(v.get())? != md5sum(&path)? {
+                                    return Err(io::Error::new(
+                                        io::ErrorKind::InvalidData,
+                                        format!(
+                                            "{} and {} are both {} of {} but differ",
+                                            v.get(),
+                                            path,
+                                            role,
+                                            prefix
+                                        ),
+                                    )
+                                    .into());
+                                }
+                                duplicates.push(Duplicate {
+                                    path,
+                                    of: v.get().to_owned(),
+                                    reason: "checksum",
+                                })
+                            }
+                            Entry::Vacant(v) => {
+                                sizes.push((
+                                    fs::metadata(&canonical)?.len(),
+                                    path.clone(),
+                                    key.to_owned(),
+                                    role.to_string(),
+                                ));
+                                v.insert(path);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // group, role and path of a collected file
+        type Source<'a> = (&'a str, &'a str, &'a str);
+        // only files of the same size may share a checksum
+        let mut checksums: HashMap<(u64, String), Source> = HashMap::new();
+        // by group, copies and the files they copy
+        let mut copies: BTreeMap<&str, Vec<(Source, Source)>> = BTreeMap::new();
+        for (size, path, key, role) in sizes.iter() {
+            if sizes.iter().filter(|v| v.0 == *size).count() < 2 {
+                continue;
+            }
+            let source: Source = (key, role, path);
+            let digest = md5sum(path)?;
+            match checksums.get(&(*size, digest.clone())) {
+                Some(of) => copies.entry(key).or_default().push((source, *of)),
+                None => {
+                    checksums.insert((*size, digest), source);
+                }
+            }
+        }
+        // a group is a duplicate only as a whole copy of one earlier group
+        for (key, copied) in copies {
+            let of_key = (copied[0].1).0;
+            let whole = copied.len() == collector[key].len()
+                && of_key != key
+                && copied
+                    .iter()
+                    .all(|(copy, of)| of.0 == of_key && copy.1 == of.1);
+            if !whole {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "inconsistent sources, {} is partly a copy: {}",
+                        key,
+                        copied
+                            .iter()
+                            .map(|(copy, of)| format!("{} of {}", copy.2, of.2))
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    ),
+                )
+                .into());
+            }
+            collector.remove(key);
+            for (copy, of) in copied {
+                duplicates.push(Duplicate {
+                    path: copy.2.to_owned(),
+                    of: of.2.to_owned(),
+                    reason: "checksum",
+                });
+            }
+        }
+
+        // the read set shared by most groups is merged, ties go to the one with more roles
+        let mut counts: BTreeMap<Vec<String>, usize> = BTreeMap::new();
+        for files in collector.values().filter(|v| v.contains_key("read1")) {
+            *counts.entry(files.keys().cloned().collect()).or_default() += 1;
+        }
+        let roles: Vec<String> = counts
+            .into_iter()
+            .max_by_key(|(k, n)| (*n, k.len()))
+            .map(|(k, _)| k)
+            .unwrap_or_default();
+        let (collector, singles): (BTreeMap<_, _>, BTreeMap<_, _>) = collector
+            .into_iter()
+            .partition(|(_, v)| v.keys().eq(roles.iter()));
+        let action = if collector.len() == 1 {
+            Action::Link
+        } else {
+            Action::Concatenate
+        };
+        let mut groups: Vec<Group> = collector
+            .into_iter()
+            .enumerate()
+            .map(|(i, (prefix, files))| Group {
+                prefix,
+                order: Some(i + 1),
+                action,
+                files,
+            })
+            .collect();
+        groups.extend(singles.into_iter().map(|(prefix, files)| Group {
+            prefix,
+            order: None,
+            action: Action::Ignore,
+            files,
+        }));
+        Ok(Self {
+            roles,
+            groups,
+            duplicates,
+        })
+    }
+
+    /// Concatenate rather than link a single group with a source not compressed like
+    /// its output, by output format of each role, returning role, source and output
+    /// format of every such source.
+    pub fn recompress(
+        &mut self,
+        formats: &BTreeMap<String, Format>,
+    ) -> io::Result<Vec<(String, Format, Format)>> {
+        let mut changes: Vec<(String, Format, Format)> = Vec::new();
+        for group in self.groups.iter_mut().filter(|g| g.action == Action::Link) {
+            for (role, format) in formats.iter() {
+                if let Some(path) = group.files.get(role) {
+                    let source = Format::detect(path)?;
+                    if source != *format {
+                        changes.push((role.to_owned(), source, *format));
+                    }
+                }
+            }
+            if !changes.is_empty() {
+                group.action = Action::Concatenate;
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Groups to merge, in merge order.
+    pub fn merged(&self) -> impl Iterator<Item = &Group> {
+        self.groups.iter().filter(|g| g.action != Action::Ignore)
+    }
+
+    /// Tab separated table, one group per row and one column per role found.
+    pub fn table(&self) -> String {
+        let roles: Vec<&str> = ROLES
+            .iter()
+            .map(|(_, role)| *role)
+            .filter(|role| self.groups.iter().any(|g| g.files.contains_key(*role)))
+            .collect();
+        let mut table = format!("order\tprefix\taction\t{}\n", roles.join("\t"));
+        for group in self.groups.iter() {
+            let files: Vec<&str> = roles
+                .iter()
+                .map(|role| group.files.get(*role).map_or("-", |v| v.as_str()))
+                .collect();
+            table.push_str(&format!(
+                "{}\t{}\t{}\t{}\n",
+                group
+                    .order
+                    .map_or_else(|| "-".to_owned(), |v| v.to_string()),
+                group.prefix,
+                group.action,
+                files.join("\t"),
+            ));
+        }
+        if !self.duplicates.is_empty() {
+            table.push_str("\nduplicate\tof\treason\n");
+            for dup in self.duplicates.iter() {
+                table.push_str(&format!("{}\t{}\t{}\n", dup.path, dup.of, dup.reason));
+            }
+        }
+        table
+    }
+}
@@ -0,0 +1,61 @@
+use bio::pattern_matching::myers::MyersBuilder;
+
+/// Nucleotide codes and the bases each stands for.
+const CODES: [(u8, &[u8]); 15] = [
+    (b'A', b"A"),
+    (b'C', b"C"),
+    (b'G', b"G"),
+    (b'T', b"T"),
+    (b'R', b"AG"),
+    (b'Y', b"CT"),
+    (b'S', b"CG"),
+    (b'W', b"AT"),
+    (b'K', b"GT"),
+    (b'M', b"AC"),
+    (b'B', b"CGT"),
+    (b'D', b"AGT"),
+    (b'H', b"ACT"),
+    (b'V', b"ACG"),
+    (b'N', b"ACGT"),
+];
+
+/// Myers builder where a pattern code matches every text code of its bases.
+///
+/// With `text` a text code matches as well when it shares any base with the pattern
+/// code, so `A` in the pattern matches `N` or `R` in the text. `U` counts as `T`.
+pub fn builder(text: bool) -> MyersBuilder {
+    let mut builder = MyersBuilder::new();
+    for &(code, bases) in CODES.iter() {
+        let mut equivalents: Vec<u8> = CODES
+            .iter()
+            .filter(|(_, other)| match text {
+                true => other.iter().any(|v| bases.contains(v)),
+                false => other.iter().all(|v| bases.contains(v)),
+            })
+            .map(|(other, _)| *other)
+            .collect();
+        if bases.contains(&b'T') {
+            equivalents.push(b'U');
+        }
+        builder.ambig(code, equivalents);
+    }
+    builder
+}
+
+/// Bases of a code, `U` as `T`, or none for other bytes.
+fn bases(code: u8) -> Option<&'static [u8]> {
+    let code = match code {
+        b'U' => b'T',
+        v => v,
+    };
+    CODES.iter().find(|v| v.0 == code).map(|v| v.1)
+}
+
+/// Whether a pattern code matches a text code, as with `builder(text)`.
+pub fn matches(pattern: u8, text_code: u8, text: bool) -> bool {
+    match (bases(pattern), bases(text_code)) {
+        (Some(p), Some(t)) if text => t.iter().any(|v| p.contains(v)),
+        (Some(p), Some(t)) => t.iter().all(|v| p.contains(v)),
+        _ => pattern == text_code,
+    }
+}
@@ -1,23 +1,401 @@
 extern crate bio;
+#[macro_use]
+extern crate clap;
 
-use std::env;
+mod iupac;
+mod output;
+mod parallel;
+mod search;
+mod seqs;
+mod trim;
 
-use bio::alignment::Alignment;
-use bio::pattern_matching::myers::long;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufWriter};
+use std::process;
+
+use bio::alphabets::dna;
+use bio::pattern_matching::myers::{long, MyersBuilder};
+use clap::{App, Arg, ArgMatches, ErrorKind};
+
+use crate::output::{Format, Output, Row};
+use crate::search::{search, Hit, Keep};
+use crate::seqs::Seq;
+use crate::trim::{Clip, Fastq, Stats, Trimmer};
+
+/// A strand of a pattern compiled for searching.
+#[derive(Clone)]
+struct Strand {
+    strand: char,
+    seq: Vec<u8>,
+    myers: long::Myers<u64>,
+}
+
+impl Strand {
+    fn new(strand: char, seq: Vec<u8>, builder: &MyersBuilder) -> Self {
+        let myers = builder.build_long_64(&seq);
+        Self { strand, seq, myers }
+    }
+}
+
+/// A pattern, its forward strand and the reverse complement if searched too.
+#[derive(Clone)]
+struct Pattern {
+    name: String,
+    strands: Vec<Strand>,
+    max_dist: usize,
+}
+
+impl Pattern {
+    /// Without `max_dist` a tenth of the length, rounded down, is allowed, and never
+    /// the whole length, which any text would match.
+    fn new(
+        name: String,
+        seq: Vec<u8>,
+        max_dist: Option<usize>,
+        both_strands: bool,
+        builder: &MyersBuilder,
+    ) -> io::Result<Self> {
+        if seq.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("pattern {} is empty", name),
+            ));
+        }
+        let max_dist = max_dist.unwrap_or(seq.len() / 10).min(seq.len() - 1);
+        let mut strands = Vec::new();
+        if both_strands {
+            strands.push(Strand::new('-', dna::revcomp(&seq), builder));
+        }
+        strands.insert(0, Strand::new('+', seq, builder));
+        Ok(Self {
+            name,
+            strands,
+            max_dist,
+        })
+    }
+
+    /// Hits of every strand, by index into `strands`, in text coordinates of the
+    /// forward strand.
+    ///
+    /// With `Keep::Best` only the best of all strands, forward first on ties.
+    fn search(&mut self, text: &[u8], keep: Keep) -> Vec<(usize, Hit)> {
+        let mut hits: Vec<(usize, Hit)> = Vec::new();
+        for (i, strand) in self.strands.iter_mut().enumerate() {
+            let found = search(&mut strand.myers, text, self.max_dist, keep);
+            hits.extend(found.into_iter().map(|v| (i, v)));
+        }
+        if keep == Keep::Best {
+            if let Some(i) = (0..hits.len()).min_by_key(|&i| hits[i].1.dist) {
+                return vec![hits.swap_remove(i)];
+            }
+        }
+        hits.sort_by_key(|(_, v)| v.start);
+        hits
+    }
+}
+
+/// Hits of every pattern in a target, by pattern and strand index.
+type Found = (Seq, Vec<(usize, usize, Hit)>);
+
+fn search_all(patterns: &mut [Pattern], target: Seq, keep: Keep) -> Found {
+    let mut hits = Vec::new();
+    for (p, pattern) in patterns.iter_mut().enumerate() {
+        let found = pattern.search(&target.seq, keep);
+        hits.extend(found.into_iter().map(|(s, hit)| (p, s, hit)));
+    }
+    (target, hits)
+}
+
+/// Targets searched by a worker at a time.
+const CHUNK: usize = 256;
+
+/// Missing or invalid arguments, reported with usage.
+#[derive(Debug)]
+struct Usage(String);
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for Usage {}
+
+fn usage(msg: &str) -> Box<dyn Error> {
+    Box::new(Usage(msg.to_string()))
+}
+
+/// Exit codes: found a match, found none, usage or other errors.
+const FOUND: i32 = 0;
+const NOT_FOUND: i32 = 1;
+const FAILED: i32 = 2;
 
 fn main() {
-    let rawp = env::args().nth(1).unwrap().to_string();
-    let rawt = env::args().nth(2).unwrap().to_string();
-    let pattern = rawp.as_bytes();
-    let text = rawt.as_bytes();
-    let mut myers = long::Myers::<u64>::new(pattern);
-    let mut aln = Alignment::default();
-    let mut matches = myers.find_all_lazy(text, 150);
-    let (best_end, _) = matches.by_ref().min_by_key(|&(_, dist)| dist).unwrap();
-    matches.alignment_at(best_end, &mut aln);
-    println!(
-        "Best alignment at {}..{} (distance: {})",
-        aln.ystart, aln.yend, aln.score
-    );
-    println!("{}", aln.pretty(pattern, text));
+    let app = App::new(crate_name!())
+        .version(crate_version!())
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about(crate_description!())
+        .args_from_usage(
+            "
+            [max_dist] -k, --max-dist=[N] 'maximum edit distance of a match, default a tenth of the pattern, less than its length'
+            [all] -a, --all 'report every non-overlapping match instead of the best one'
+            [best] -b, --best-per-location 'report the best match of every run of overlapping matches'
+            [both] -s, --both-strands 'also search the reverse complement of each pattern'
+            [iupac] -i, --iupac 'iupac codes of patterns match every base they stand for'
+            [iupac_text] -I, --iupac-text 'like --iupac, and text codes match every pattern base they share'
+            [format] -o, --format=[FORMAT] 'text, tsv, bed or sam, default text for strings and tsv for files'
+            [threads] -j, --threads=[N] 'search targets on N threads, default 1'
+            [patterns] -p, --patterns=[FASTA] 'fasta of patterns, instead of PATTERN'
+            [primers] --primers=[FASTA] '5\' primers of reads to trim, reads are cut after the best one'
+            [adapters] --adapters=[FASTA] '3\' adapters of reads to trim, reads are cut before the best one'
+            [discard] --discard-untrimmed 'drop reads, and pairs, where nothing was trimmed'
+            [PATTERN] 'pattern to search for'
+            [TEXT] 'text to search in'
+            ",
+        )
+        .arg(
+            Arg::from_usage(
+                "[output] -O, --output=[FASTQ]... 'trimmed reads, gzipped for .gz, default stdout; twice for pairs'",
+            )
+            .number_of_values(1),
+        )
+        .arg(
+            Arg::from_usage(
+                "[targets] -t, --targets=[FILE]... 'fasta or fastq targets, gzipped or not, `-` for stdin, instead of TEXT; repeat for more'",
+            )
+            .number_of_values(1),
+        )
+        ;
+    let help = app.clone();
+    let args = match app.get_matches_safe() {
+        Ok(v) => v,
+        Err(e) => match e.kind {
+            ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed => e.exit(),
+            _ => {
+                eprintln!("{}", e.message);
+                process::exit(FAILED);
+            }
+        },
+    };
+    match run(&args) {
+        Ok(true) => process::exit(FOUND),
+        Ok(false) => process::exit(NOT_FOUND),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            if e.is::<Usage>() {
+                eprintln!();
+                help.write_help(&mut io::stderr()).ok();
+                eprintln!();
+            }
+            process::exit(FAILED);
+        }
+    }
+}
+
+/// Whether iupac codes of patterns match, and of the text too if true.
+fn iupac(args: &ArgMatches) -> Option<bool> {
+    if args.is_present("iupac_text") {
+        Some(true)
+    } else if args.is_present("iupac") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Myers builder for the iupac flags given.
+fn builder(args: &ArgMatches) -> MyersBuilder {
+    match iupac(args) {
+        Some(text) => iupac::builder(text),
+        None => MyersBuilder::new(),
+    }
+}
+
+/// Search and report, true if anything matched.
+fn run(args: &ArgMatches) -> Result<bool, Box<dyn Error>> {
+    let max_dist: Option<usize> = match args.value_of("max_dist").map(str::parse) {
+        Some(Ok(v)) => Some(v),
+        Some(Err(_)) => return Err(usage("--max-dist expects a number")),
+        None => None,
+    };
+    let keep = if args.is_present("best") {
+        Keep::BestPerLocation
+    } else if args.is_present("all") {
+        Keep::All
+    } else {
+        Keep::Best
+    };
+    let threads: usize = match args.value_of("threads").unwrap_or("1").parse() {
+        Ok(v) if v > 0 => v,
+        _ => return Err(usage("--threads expects a positive number")),
+    };
+    if args.is_present("primers") || args.is_present("adapters") {
+        // exit codes tell matches only when searching, trimming succeeds or fails
+        trim(args, max_dist, threads)?;
+        return Ok(true);
+    }
+    let both_strands: bool = args.is_present("both");
+    let builder = builder(args);
+    // strings given take the place of whichever file is missing
+    let mut strings = args
+        .values_of("PATTERN")
+        .into_iter()
+        .flatten()
+        .chain(args.values_of("TEXT").into_iter().flatten());
+
+    let mut patterns: Vec<Pattern> = Vec::new();
+    match args.value_of("patterns") {
+        Some(path) => {
+            for seq in seqs::open(path)? {
+                let seq = seq?;
+                patterns.push(Pattern::new(
+                    seq.name,
+                    seq.seq,
+                    max_dist,
+                    both_strands,
+                    &builder,
+                )?);
+            }
+        }
+        None => match strings.next() {
+            Some(v) => patterns.push(Pattern::new(
+                "pattern".to_string(),
+                v.into(),
+                max_dist,
+                both_strands,
+                &builder,
+            )?),
+            None => return Err(usage("expect PATTERN or --patterns")),
+        },
+    }
+    let text = strings.next();
+    let files = args.is_present("patterns") || args.is_present("targets");
+    let format: Format = match args.value_of("format") {
+        Some(v) => v.parse().map_err(|e: io::Error| usage(&e.to_string()))?,
+        // a single string pair is explained in prose
+        None if files => Format::Tsv,
+        None => Format::Text,
+    };
+
+    let targets: Vec<seqs::Seqs> = match args.values_of("targets") {
+        Some(paths) => paths.map(seqs::open).collect::<io::Result<_>>()?,
+        None => match text {
+            Some(v) => vec![Box::new(std::iter::once(Ok(Seq {
+                name: "text".to_string(),
+                desc: None,
+                seq: v.into(),
+                qual: Vec::new(),
+            })))],
+            None => return Err(usage("expect TEXT or --targets")),
+        },
+    };
+    let stdout = io::stdout();
+    let mut output = Output::new(
+        format,
+        BufWriter::new(stdout.lock()),
+        files,
+        both_strands,
+        keep == Keep::Best,
+        patterns.iter().map(|v| v.max_dist).max().unwrap_or(0),
+    )?;
+    // patterns are compiled once, every worker searches with its own copy
+    parallel::ordered_map(
+        targets.into_iter().flatten(),
+        threads,
+        CHUNK,
+        &patterns,
+        |patterns, target| search_all(patterns, target, keep),
+        |(target, hits)| {
+            output.target(&target.name, target.seq.len());
+            for (p, s, hit) in hits {
+                let (pattern, strand) = (&patterns[p], &patterns[p].strands[s]);
+                output.hit(&Row {
+                    pattern: &pattern.name,
+                    target: &target.name,
+                    strand: strand.strand,
+                    query: &strand.seq,
+                    text: &target.seq,
+                    hit: &hit,
+                })?;
+            }
+            Ok(())
+        },
+    )?;
+    Ok(output.finish()?)
+}
+
+/// Trim primers and adapters off fastq reads.
+fn trim(args: &ArgMatches, max_dist: Option<usize>, threads: usize) -> Result<(), Box<dyn Error>> {
+    if args.is_present("PATTERN") || args.is_present("patterns") {
+        return Err(usage(
+            "trimming takes patterns from --primers and --adapters",
+        ));
+    }
+    let iupac = iupac(args);
+    let clips = |arg: &str| -> io::Result<Vec<Clip>> {
+        match args.value_of(arg) {
+            Some(path) => seqs::open(path)?
+                .map(|v| v.and_then(|seq| Clip::new(seq, max_dist, iupac)))
+                .collect(),
+            None => Ok(Vec::new()),
+        }
+    };
+    let trimmer = Trimmer {
+        primers: clips("primers")?,
+        adapters: clips("adapters")?,
+    };
+    let paths: Vec<&str> = args.values_of("targets").into_iter().flatten().collect();
+    if paths.is_empty() || paths.len() > 2 {
+        return Err(usage(
+            "trimming expects reads, or pairs of them, from --targets",
+        ));
+    }
+    let mates = match paths.get(1) {
+        Some(v) => Some(seqs::open(v)?),
+        None => None,
+    };
+    let reads = trim::reads(seqs::open(paths[0])?, mates);
+    let outputs: Vec<&str> = match args.values_of("output") {
+        Some(v) => v.collect(),
+        None if paths.len() == 1 => vec!["-"],
+        None => return Err(usage("pairs expect two --output files")),
+    };
+    if outputs.len() != paths.len() {
+        return Err(usage("expect an --output for every file of --targets"));
+    }
+    let mut outputs: Vec<Fastq> = outputs
+        .into_iter()
+        .map(Fastq::create)
+        .collect::<io::Result<_>>()?;
+    let discard = args.is_present("discard");
+    let mut stats = Stats::new(&trimmer);
+    eprintln!("Trimming {} ...", paths.join(" and "));
+    // mates are trimmed and written, or discarded, together
+    parallel::ordered_map(
+        reads,
+        threads,
+        CHUNK,
+        &trimmer,
+        |trimmer, mut mates| {
+            let cuts: Vec<_> = mates.iter_mut().map(|v| trimmer.trim(v)).collect();
+            (mates, cuts)
+        },
+        |(mates, cuts)| {
+            cuts.iter().for_each(|v| stats.add(v));
+            if discard && !cuts.iter().any(|v| v.trimmed()) {
+                stats.discarded += mates.len();
+                return Ok(());
+            }
+            for (output, read) in outputs.iter_mut().zip(mates.iter()) {
+                output.write(read)?;
+            }
+            Ok(())
+        },
+    )?;
+    for output in outputs {
+        output.finish()?;
+    }
+    stats.write(io::stderr(), &trimmer)?;
+    Ok(())
 }
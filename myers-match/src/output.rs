@@ -0,0 +1,316 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::str::FromStr;
+
+use bio::alignment::AlignmentOperation;
+
+use crate::search::Hit;
+
+/// How hits are written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// prose and pretty alignment
+    Text,
+    /// one hit per row
+    Tsv,
+    /// hits as bed6 features on the targets, distance as score
+    Bed,
+    /// patterns as reads aligned to targets as references
+    Sam,
+}
+
+impl FromStr for Format {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        match s {
+            "text" => Ok(Format::Text),
+            "tsv" => Ok(Format::Tsv),
+            "bed" => Ok(Format::Bed),
+            "sam" => Ok(Format::Sam),
+            v => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "unknown format `{}`, expect `text`, `tsv`, `bed` or `sam`",
+                    v
+                ),
+            )),
+        }
+    }
+}
+
+/// A hit with what it was found by and in.
+pub struct Row<'a> {
+    pub pattern: &'a str,
+    pub target: &'a str,
+    pub strand: char,
+    /// pattern as searched, reverse complemented on the `-` strand
+    pub query: &'a [u8],
+    pub text: &'a [u8],
+    pub hit: &'a Hit,
+}
+
+/// Mismatching positions and deleted reference bases of a hit, the sam MD tag.
+fn md(row: &Row) -> String {
+    let mut md = String::new();
+    let mut matched: usize = 0;
+    let mut y = row.hit.start;
+    let mut deleting = false;
+    for op in row.hit.aln.operations.iter() {
+        match op {
+            AlignmentOperation::Match => {
+                matched += 1;
+                y += 1;
+                deleting = false;
+            }
+            AlignmentOperation::Subst => {
+                md.push_str(&matched.to_string());
+                md.push(row.text[y] as char);
+                matched = 0;
+                y += 1;
+                deleting = false;
+            }
+            AlignmentOperation::Del => {
+                if !deleting {
+                    md.push_str(&matched.to_string());
+                    md.push('^');
+                    matched = 0;
+                    deleting = true;
+                }
+                md.push(row.text[y] as char);
+                y += 1;
+            }
+            _ => deleting = false,
+        }
+    }
+    md.push_str(&matched.to_string());
+    md
+}
+
+/// Writes hits in a format.
+///
+/// Sam records are spooled to a temporary file until the targets with hits, the
+/// header references, are all known.
+pub struct Output<W: Write> {
+    format: Format,
+    writer: W,
+    /// label hits with pattern and target names in text
+    names: bool,
+    /// label hits with their strand in text
+    strands: bool,
+    /// only the best hit of each pattern and target
+    best: bool,
+    /// largest distance any pattern is allowed
+    max_dist: usize,
+    found: bool,
+    /// current target, and whether it has hits yet
+    target: (String, usize, bool),
+    /// targets with hits
+    targets: Vec<(String, usize)>,
+    records: Option<BufWriter<File>>,
+    primary: HashSet<String>,
+}
+
+impl<W: Write> Output<W> {
+    pub fn new(
+        format: Format,
+        mut writer: W,
+        names: bool,
+        strands: bool,
+        best: bool,
+        max_dist: usize,
+    ) -> io::Result<Self> {
+        if format == Format::Tsv {
+            writeln!(
+                writer,
+                "#pattern\ttarget\tstrand\tstart\tend\tdistance\tcigar"
+            )?;
+        }
+        Ok(Self {
+            format,
+            writer,
+            names,
+            strands,
+            best,
+            max_dist,
+            found: false,
+            target: (String::new(), 0, false),
+            targets: Vec::new(),
+            records: match format {
+                Format::Sam => Some(BufWriter::new(tempfile::tempfile()?)),
+                _ => None,
+            },
+            primary: HashSet::new(),
+        })
+    }
+
+    /// Register a target before its hits, a sam reference once it has any.
+    pub fn target(&mut self, name: &str, len: usize) {
+        if self.format == Format::Sam {
+            self.target = (name.to_string(), len, false);
+        }
+    }
+
+    pub fn hit(&mut self, row: &Row) -> io::Result<()> {
+        self.found = true;
+        let hit = row.hit;
+        match self.format {
+            Format::Text => {
+                writeln!(
+                    self.writer,
+                    "{}lignment{} at {}..{}{} (distance: {})",
+                    if self.best { "Best a" } else { "A" },
+                    match self.names {
+                        true => format!(" of {} in {}", row.pattern, row.target),
+                        false => String::new(),
+                    },
+                    hit.start,
+                    hit.end,
+                    match self.strands {
+                        true => format!(" on strand {}", row.strand),
+                        false => String::new(),
+                    },
+                    hit.dist
+                )?;
+                writeln!(self.writer, "{}", hit.aln.pretty(row.query, row.text, 100))?;
+            }
+            Format::Tsv => writeln!(
+                self.writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                row.pattern,
+                row.target,
+                row.strand,
+                hit.start,
+                hit.end,
+                hit.dist,
+                hit.aln.cigar(false)
+            )?,
+            Format::Bed => writeln!(
+                self.writer,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                row.target, hit.start, hit.end, row.pattern, hit.dist, row.strand
+            )?,
+            Format::Sam => {
+                if !self.target.2 {
+                    self.target.2 = true;
+                    self.targets.push((self.target.0.to_owned(), self.target.1));
+                }
+                // first hit of a pattern is primary, later ones secondary
+                let mut flag: u16 = if row.strand == '-' { 0x10 } else { 0 };
+                if !self.primary.insert(row.pattern.to_string()) {
+                    flag |= 0x100;
+                }
+                if let Some(records) = self.records.as_mut() {
+                    writeln!(
+                        records,
+                        "{}\t{}\t{}\t{}\t255\t{}\t*\t0\t0\t{}\t*\tNM:i:{}\tMD:Z:{}",
+                        row.pattern,
+                        flag,
+                        row.target,
+                        hit.start + 1,
+                        hit.aln.cigar(false),
+                        String::from_utf8_lossy(row.query),
+                        hit.dist,
+                        md(row)
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush everything, returns whether anything was found.
+    pub fn finish(mut self) -> io::Result<bool> {
+        match self.format {
+            Format::Text if !self.found => {
+                writeln!(
+                    self.writer,
+                    "No match (maximum distance: {})",
+                    self.max_dist
+                )?;
+            }
+            Format::Sam => {
+                writeln!(self.writer, "@HD\tVN:1.6\tSO:unsorted")?;
+                for (name, len) in self.targets.iter() {
+                    writeln!(self.writer, "@SQ\tSN:{}\tLN:{}", name, len)?;
+                }
+                writeln!(
+                    self.writer,
+                    "@PG\tID:{}\tPN:{}\tVN:{}",
+                    env!("CARGO_PKG_NAME"),
+                    env!("CARGO_PKG_NAME"),
+                    env!("CARGO_PKG_VERSION")
+                )?;
+                if let Some(records) = self.records.take() {
+                    let mut records = records.into_inner().map_err(|e| e.into_error())?;
+                    records.seek(SeekFrom::Start(0))?;
+                    io::copy(&mut records, &mut self.writer)?;
+                }
+            }
+            _ => {}
+        }
+        self.writer.flush()?;
+        Ok(self.found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bio::alignment::Alignment;
+    use bio::pattern_matching::myers::MyersBuilder;
+
+    use super::*;
+    use crate::search::{search, Keep};
+
+    /// MD tag of the best hit of `query` in `text`.
+    fn best_md(query: &[u8], text: &[u8], max_dist: usize) -> String {
+        let mut myers = MyersBuilder::new().build_long_64(query);
+        let hits = search(&mut myers, text, max_dist, Keep::Best);
+        md(&Row {
+            pattern: "pattern",
+            target: "text",
+            strand: '+',
+            query,
+            text,
+            hit: &hits[0],
+        })
+    }
+
+    #[test]
+    fn md_of_matches_and_substitutions() {
+        assert_eq!(best_md(b"GATTACAGATTACA", b"CCGATTACAGATTACACC", 0), "14");
+        assert_eq!(best_md(b"GATTACAGATTACA", b"CCGATTTCAGATTGCACC", 2), "4T6G2");
+    }
+
+    #[test]
+    fn md_of_deletions_and_insertions() {
+        // text bases missing from the query are deleted, query bases missing from
+        // the text are not in MD
+        assert_eq!(best_md(b"GATTACAGATTACA", b"CCGATTACACCGATTACACC", 2), "7^CC7");
+        assert_eq!(best_md(b"GATTACAGATTACA", b"CCGATTACGATTACACC", 1), "13");
+    }
+
+    #[test]
+    fn md_of_substitution_after_deletion() {
+        use AlignmentOperation::*;
+        let hit = Hit {
+            start: 1,
+            end: 7,
+            dist: 2,
+            aln: Alignment {
+                operations: vec![Match, Match, Del, Subst, Match, Match],
+                ..Default::default()
+            },
+        };
+        let row = Row {
+            pattern: "pattern",
+            target: "text",
+            strand: '+',
+            query: b"ACTTT",
+            text: b"GACGTTGG",
+            hit: &hit,
+        };
+        assert_eq!(md(&row), "2^G0T2");
+    }
+}
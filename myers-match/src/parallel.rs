@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Mutex;
+use std::thread;
+
+/// Results of chunks in input order, however workers finish them.
+struct Ordered<R> {
+    pending: BTreeMap<usize, Vec<R>>,
+    next: usize,
+}
+
+impl<R> Ordered<R> {
+    fn push<E, W: FnMut(R) -> Result<(), E>>(
+        &mut self,
+        index: usize,
+        results: Vec<R>,
+        write: &mut W,
+    ) -> Result<(), E> {
+        self.pending.insert(index, results);
+        while let Some(results) = self.pending.remove(&self.next) {
+            for v in results {
+                write(v)?;
+            }
+            self.next += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Send chunks of items to workers, writing results as they come in order.
+///
+/// Owns the sender so workers stop whenever feeding ends, by error or not.
+fn feed<T, R, E, I, W>(
+    mut items: I,
+    chunk: usize,
+    work: SyncSender<(usize, Vec<T>)>,
+    done: &Receiver<(usize, Vec<R>)>,
+    write: &mut W,
+) -> Result<(), E>
+where
+    I: Iterator<Item = Result<T, E>>,
+    W: FnMut(R) -> Result<(), E>,
+{
+    let mut ordered = Ordered {
+        pending: BTreeMap::new(),
+        next: 0,
+    };
+    for index in 0.. {
+        let mut batch: Vec<T> = Vec::with_capacity(chunk);
+        for item in items.by_ref().take(chunk) {
+            batch.push(item?);
+        }
+        if batch.is_empty() || work.send((index, batch)).is_err() {
+            break;
+        }
+        for (i, results) in done.try_iter() {
+            ordered.push(i, results, write)?;
+        }
+    }
+    drop(work);
+    for (i, results) in done.iter() {
+        ordered.push(i, results, write)?;
+    }
+    Ok(())
+}
+
+/// Map items with `f` on `threads` workers in chunks of `chunk`, each worker with its
+/// own clone of `state`, and hand the results to `write` in input order.
+pub fn ordered_map<T, R, S, E, I, F, W>(
+    items: I,
+    threads: usize,
+    chunk: usize,
+    state: &S,
+    f: F,
+    mut write: W,
+) -> Result<(), E>
+where
+    T: Send,
+    R: Send,
+    S: Clone + Sync,
+    I: Iterator<Item = Result<T, E>>,
+    F: Fn(&mut S, T) -> R + Sync,
+    W: FnMut(R) -> Result<(), E>,
+{
+    let (work_tx, work_rx) = mpsc::sync_channel::<(usize, Vec<T>)>(threads * 2);
+    let (done_tx, done_rx) = mpsc::channel::<(usize, Vec<R>)>();
+    let work_rx = Mutex::new(work_rx);
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            let (work_rx, done_tx, f) = (&work_rx, done_tx.clone(), &f);
+            scope.spawn(move || {
+                let mut state = state.clone();
+                loop {
+                    // the lock is only held while waiting for the next chunk
+                    let next = work_rx.lock().unwrap().recv();
+                    let (index, batch) = match next {
+                        Ok(v) => v,
+                        Err(_) => break,
+                    };
+                    let results = batch.into_iter().map(|v| f(&mut state, v)).collect();
+                    if done_tx.send((index, results)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(done_tx);
+        feed(items, chunk, work_tx, &done_rx, &mut write)
+    })
+}
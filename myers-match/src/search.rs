@@ -0,0 +1,105 @@
+use std::slice::Iter;
+
+use bio::alignment::Alignment;
+use bio::pattern_matching::myers::long::{LazyMatches, Myers};
+
+/// A match of a pattern in a text, `start..end` 0-based half open text coordinates.
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub start: usize,
+    pub end: usize,
+    pub dist: usize,
+    pub aln: Alignment,
+}
+
+/// Which matches at or below the maximum distance are reported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Keep {
+    /// the single best match, leftmost on ties
+    Best,
+    /// the best match of every run of adjacent ends not overlapping an earlier one,
+    /// left to right
+    All,
+    /// the best match of every run of overlapping matches
+    BestPerLocation,
+}
+
+/// Search `text` for the pattern of `myers` within `max_dist` edits.
+///
+/// Ends are taken as they are found and aligned only when kept, so no more than
+/// the hits themselves is held, however long the text.
+pub fn search(myers: &mut Myers<u64>, text: &[u8], max_dist: usize, keep: Keep) -> Vec<Hit> {
+    let mut matches = myers.find_all_lazy(text, max_dist);
+    let mut hits: Vec<Hit> = Vec::new();
+    // ends already searched can be aligned while the search goes on
+    let at = |matches: &LazyMatches<u64, &u8, Iter<u8>>, end: usize, dist: usize| {
+        let mut aln = Alignment::default();
+        matches.alignment_at(end, &mut aln);
+        Hit {
+            start: aln.ystart,
+            end: aln.yend,
+            dist,
+            aln,
+        }
+    };
+    match keep {
+        Keep::Best => {
+            if let Some((end, dist)) = matches.by_ref().min_by_key(|&(_, dist)| dist) {
+                hits.push(at(&matches, end, dist));
+            }
+        }
+        Keep::All => {
+            // end of the last reported hit
+            let mut last: usize = 0;
+            let mut report = |hit: Hit| {
+                if hits.is_empty() || hit.start >= last {
+                    last = hit.end;
+                    hits.push(hit);
+                }
+            };
+            // best end of the current run of consecutive ends, rightmost on ties
+            let mut best: Option<(usize, usize)> = None;
+            let mut prev: Option<usize> = None;
+            while let Some((end, dist)) = matches.next() {
+                match best.as_mut() {
+                    Some(v) if prev.is_some_and(|p| p + 1 == end) => {
+                        if dist <= v.1 {
+                            *v = (end, dist);
+                        }
+                    }
+                    _ => {
+                        if let Some((end, dist)) = best {
+                            report(at(&matches, end, dist));
+                        }
+                        best = Some((end, dist));
+                    }
+                }
+                prev = Some(end);
+            }
+            if let Some((end, dist)) = best {
+                report(at(&matches, end, dist));
+            }
+        }
+        Keep::BestPerLocation => {
+            // end of the run of overlapping matches the last hit is the best of
+            let mut run: usize = 0;
+            while let Some((end, dist)) = matches.next() {
+                let hit = at(&matches, end, dist);
+                let start = hit.start;
+                match hits.last_mut() {
+                    Some(v) if start < run => {
+                        run = run.max(hit.end);
+                        if hit.dist < v.dist {
+                            *v = hit;
+                        }
+                    }
+                    _ => {
+                        run = hit.end;
+                        hits.push(hit);
+                    }
+                }
+            }
+        }
+    }
+    hits
+}
@@ -0,0 +1,56 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+
+use bio::io::{fasta, fastq};
+use flate2::read::MultiGzDecoder;
+
+/// A fasta or fastq record, `qual` empty for fasta.
+#[derive(Debug, Clone)]
+pub struct Seq {
+    pub name: String,
+    pub desc: Option<String>,
+    pub seq: Vec<u8>,
+    pub qual: Vec<u8>,
+}
+
+pub type Seqs = Box<dyn Iterator<Item = io::Result<Seq>>>;
+
+fn invalid<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// Read fasta or fastq records, plain or gzipped, from a file or `-` for stdin.
+///
+/// The format is told by the first byte, `>` for fasta and `@` for fastq.
+pub fn open(path: &str) -> io::Result<Seqs> {
+    let reader: Box<dyn Read> = match path {
+        "-" => Box::new(io::stdin()),
+        _ => Box::new(File::open(path)?),
+    };
+    let mut reader = BufReader::new(reader);
+    if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        reader = BufReader::new(Box::new(MultiGzDecoder::new(reader)));
+    }
+    let seqs: Seqs = match reader.fill_buf()?.first() {
+        Some(b'>') => Box::new(fasta::Reader::new(reader).records().map(|v| {
+            v.map(|r| Seq {
+                name: r.id().to_string(),
+                desc: r.desc().map(String::from),
+                seq: r.seq().to_vec(),
+                qual: Vec::new(),
+            })
+        })),
+        Some(b'@') => Box::new(fastq::Reader::new(reader).records().map(|v| {
+            v.map(|r| Seq {
+                name: r.id().to_string(),
+                desc: r.desc().map(String::from),
+                seq: r.seq().to_vec(),
+                qual: r.qual().to_vec(),
+            })
+            .map_err(invalid)
+        })),
+        Some(_) => return Err(invalid(format!("{} is neither fasta nor fastq", path))),
+        None => Box::new(std::iter::empty()),
+    };
+    Ok(seqs)
+}
@@ -0,0 +1,315 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use bio::alignment::pairwise::{Aligner, Scoring};
+use bio::alignment::AlignmentOperation;
+use bio::pattern_matching::myers::{long::Myers, MyersBuilder};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::iupac;
+use crate::search::{search, Hit, Keep};
+use crate::seqs::Seq;
+
+/// Shortest start of an adapter trimmed off the end of a read.
+const MIN_OVERLAP: usize = 3;
+
+/// A 5' primer or 3' adapter compiled for trimming.
+#[derive(Clone)]
+pub struct Clip {
+    pub name: String,
+    seq: Vec<u8>,
+    myers: Myers<u64>,
+    max_dist: usize,
+    /// iupac codes of the pattern, and of the text too if true
+    iupac: Option<bool>,
+}
+
+impl Clip {
+    /// Without `max_dist` a tenth of the length, rounded down, is allowed.
+    pub fn new(seq: Seq, max_dist: Option<usize>, iupac: Option<bool>) -> io::Result<Self> {
+        if seq.seq.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("pattern {} is empty", seq.name),
+            ));
+        }
+        let builder = match iupac {
+            Some(text) => iupac::builder(text),
+            None => MyersBuilder::new(),
+        };
+        Ok(Self {
+            max_dist: max_dist.unwrap_or(seq.seq.len() / 10),
+            myers: builder.build_long_64(&seq.seq),
+            seq: seq.seq,
+            name: seq.name,
+            iupac,
+        })
+    }
+
+    /// Start of a read-through into the clip at the end of `text`, a prefix of
+    /// at least `MIN_OVERLAP` bases with edits scaled down to its length, and
+    /// the alignment score.
+    fn overlap(&self, text: &[u8]) -> Option<(usize, i32)> {
+        let iupac = self.iupac;
+        let score = move |a: u8, b: u8| {
+            let same = match iupac {
+                Some(text) => iupac::matches(a, b, text),
+                None => a == b,
+            };
+            if same {
+                1
+            } else {
+                -1
+            }
+        };
+        // clip start anchored, its end and the text start free, the text end reached
+        let scoring = Scoring::new(0, -2, score).xclip_suffix(0).yclip_prefix(0);
+        let tail = text.len().saturating_sub(self.seq.len() + self.max_dist);
+        let aln = Aligner::with_scoring(scoring).custom(&self.seq, &text[tail..]);
+        let edits = aln
+            .operations
+            .iter()
+            .filter(|v| {
+                !matches!(
+                    v,
+                    AlignmentOperation::Match
+                        | AlignmentOperation::Xclip(_)
+                        | AlignmentOperation::Yclip(_)
+                )
+            })
+            .count();
+        if aln.xend < MIN_OVERLAP || edits > aln.xend * self.max_dist / self.seq.len() {
+            return None;
+        }
+        Some((tail + aln.ystart, aln.score))
+    }
+
+    /// Start of `text` a primer at its 5' end can span, with every edit an insertion.
+    fn head<'a>(&self, text: &'a [u8]) -> &'a [u8] {
+        &text[..text.len().min(self.seq.len() + self.max_dist)]
+    }
+}
+
+/// The best hit of any clip, least distance then leftmost, with the clip index.
+///
+/// With `head` each clip is only looked for at the start of `text`.
+fn best(clips: &mut [Clip], text: &[u8], head: bool) -> Option<(usize, Hit)> {
+    let mut best: Option<(usize, Hit)> = None;
+    for (i, clip) in clips.iter_mut().enumerate() {
+        let text = if head { clip.head(text) } else { text };
+        for hit in search(&mut clip.myers, text, clip.max_dist, Keep::Best) {
+            let better = match &best {
+                Some((_, v)) => (hit.dist, hit.start) < (v.dist, v.start),
+                None => true,
+            };
+            if better {
+                best = Some((i, hit));
+            }
+        }
+    }
+    best
+}
+
+/// What was cut off a read, by clip index and number of bases.
+#[derive(Debug, Default)]
+pub struct Cut {
+    pub primer: Option<(usize, usize)>,
+    pub adapter: Option<(usize, usize)>,
+}
+
+impl Cut {
+    pub fn trimmed(&self) -> bool {
+        self.primer.is_some() || self.adapter.is_some()
+    }
+}
+
+/// Trims reads after the best 5' primer and before the best 3' adapter.
+#[derive(Clone)]
+pub struct Trimmer {
+    pub primers: Vec<Clip>,
+    pub adapters: Vec<Clip>,
+}
+
+impl Trimmer {
+    /// Trim sequence and qualities of `read`, the adapter searched after the primer.
+    ///
+    /// Primers are only looked for at the 5' end, within their length and edits.
+    /// Only if no adapter is found whole is one running off the end looked for.
+    pub fn trim(&mut self, read: &mut Seq) -> Cut {
+        let mut cut = Cut::default();
+        if let Some((i, hit)) = best(&mut self.primers, &read.seq, true) {
+            read.seq.drain(..hit.end);
+            read.qual.drain(..hit.end);
+            cut.primer = Some((i, hit.end));
+        }
+        // a whole adapter, or else one running off the end of the read
+        let start = match best(&mut self.adapters, &read.seq, false) {
+            Some((i, hit)) => Some((i, hit.start)),
+            None => self
+                .adapters
+                .iter()
+                .enumerate()
+                .filter_map(|(i, clip)| clip.overlap(&read.seq).map(|(v, score)| (i, v, score)))
+                .max_by_key(|&(_, _, score)| score)
+                .map(|(i, v, _)| (i, v)),
+        };
+        if let Some((i, start)) = start {
+            cut.adapter = Some((i, read.seq.len() - start));
+            read.seq.truncate(start);
+            read.qual.truncate(start);
+        }
+        cut
+    }
+}
+
+/// Reads and bases trimmed, in total and by each primer and adapter.
+pub struct Stats {
+    pub reads: usize,
+    pub trimmed: usize,
+    pub discarded: usize,
+    /// reads and bases by clip index
+    primers: Vec<(usize, usize)>,
+    adapters: Vec<(usize, usize)>,
+}
+
+impl Stats {
+    pub fn new(trimmer: &Trimmer) -> Self {
+        Self {
+            reads: 0,
+            trimmed: 0,
+            discarded: 0,
+            primers: vec![(0, 0); trimmer.primers.len()],
+            adapters: vec![(0, 0); trimmer.adapters.len()],
+        }
+    }
+
+    pub fn add(&mut self, cut: &Cut) {
+        self.reads += 1;
+        if cut.trimmed() {
+            self.trimmed += 1;
+        }
+        if let Some((i, bases)) = cut.primer {
+            self.primers[i].0 += 1;
+            self.primers[i].1 += bases;
+        }
+        if let Some((i, bases)) = cut.adapter {
+            self.adapters[i].0 += 1;
+            self.adapters[i].1 += bases;
+        }
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W, trimmer: &Trimmer) -> io::Result<()> {
+        writeln!(
+            writer,
+            "Trimmed {} of {} reads, discarded {}",
+            self.trimmed, self.reads, self.discarded
+        )?;
+        writeln!(writer, "#pattern\tend\treads\tbases")?;
+        let rows = trimmer
+            .primers
+            .iter()
+            .zip(self.primers.iter())
+            .map(|(clip, v)| (clip, "5'", v))
+            .chain(
+                trimmer
+                    .adapters
+                    .iter()
+                    .zip(self.adapters.iter())
+                    .map(|(clip, v)| (clip, "3'", v)),
+            );
+        for (clip, end, (reads, bases)) in rows {
+            writeln!(writer, "{}\t{}\t{}\t{}", clip.name, end, reads, bases)?;
+        }
+        Ok(())
+    }
+}
+
+/// A fastq file being written, gzipped when its name ends with `.gz`, `-` for stdout.
+pub enum Fastq {
+    Plain(BufWriter<Box<dyn Write>>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl Fastq {
+    pub fn create(path: &str) -> io::Result<Self> {
+        Ok(match path {
+            "-" => Fastq::Plain(BufWriter::new(Box::new(io::stdout()))),
+            v if v.ends_with(".gz") => Fastq::Gzip(GzEncoder::new(
+                BufWriter::new(File::create(v)?),
+                Compression::default(),
+            )),
+            v => Fastq::Plain(BufWriter::new(Box::new(File::create(v)?))),
+        })
+    }
+
+    pub fn write(&mut self, read: &Seq) -> io::Result<()> {
+        let writer: &mut dyn Write = match self {
+            Fastq::Plain(v) => v,
+            Fastq::Gzip(v) => v,
+        };
+        match &read.desc {
+            Some(desc) => writeln!(writer, "@{} {}", read.name, desc)?,
+            None => writeln!(writer, "@{}", read.name)?,
+        }
+        writer.write_all(&read.seq)?;
+        writer.write_all(b"\n+\n")?;
+        writer.write_all(&read.qual)?;
+        writer.write_all(b"\n")
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Fastq::Plain(mut v) => v.flush(),
+            Fastq::Gzip(v) => v.finish()?.flush(),
+        }
+    }
+}
+
+/// Name of a mate without its `/1` or `/2` suffix.
+fn mate_name(name: &str) -> &str {
+    name.strip_suffix("/1")
+        .or_else(|| name.strip_suffix("/2"))
+        .unwrap_or(name)
+}
+
+/// A read with a quality for every base, which fasta records lack.
+fn fastq(read: Seq) -> io::Result<Seq> {
+    if read.qual.len() != read.seq.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} has no qualities, trimming expects fastq", read.name),
+        ));
+    }
+    Ok(read)
+}
+
+/// Reads, or pairs of mates when `mates` is given, refusing mates out of sync.
+pub fn reads(
+    mut reads: impl Iterator<Item = io::Result<Seq>>,
+    mut mates: Option<impl Iterator<Item = io::Result<Seq>>>,
+) -> impl Iterator<Item = io::Result<Vec<Seq>>> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    std::iter::from_fn(move || {
+        let read = reads.next();
+        let mates = match mates.as_mut() {
+            Some(v) => v,
+            None => return read.map(|v| v.and_then(fastq).map(|read| vec![read])),
+        };
+        let pair = match (read, mates.next()) {
+            (None, None) => return None,
+            (Some(read), Some(mate)) => read.and_then(|read| mate.map(|mate| (read, mate))),
+            (Some(_), None) => Err(invalid("second reads end before first reads".to_string())),
+            (None, Some(_)) => Err(invalid("first reads end before second reads".to_string())),
+        };
+        Some(pair.and_then(|(read, mate)| {
+            if mate_name(&read.name) != mate_name(&mate.name) {
+                return Err(invalid(format!(
+                    "mates out of sync, {} paired with {}",
+                    read.name, mate.name
+                )));
+            }
+            Ok(vec![fastq(read)?, fastq(mate)?])
+        }))
+    })
+}